@@ -1,8 +1,9 @@
 use core::fmt;
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use crate::rclone::{RFileInfo};
-use crate::ledger::Ledger;
+use crate::backend::RFileInfo;
+use crate::ledger::{Ledger, LedgerInfo};
 
 #[derive(PartialEq, Eq, Debug, Hash, Clone)]
 pub enum ActionType {
@@ -22,18 +23,30 @@ impl fmt::Display for ActionType {
             ActionType::Local2Remote => { write!(f, "copy Local -> Remote") }
             ActionType::Remote2Local => { write!(f, "copy Remote -> Local") }
             ActionType::Error(err) => {write!(f, "{}", err)}
-            _ => unimplemented!()
+            ActionType::Nothing =>      { write!(f, "Nothing") }
         }
     }
 }
 
 impl ActionType {
     pub fn is_error(&self) -> bool {
-        return match self { ActionType::Error(_) => true, _ => false }
+        return matches!(self, ActionType::Error(_))
+    }
+
+    /// Stable name of the action type, used by the sync history
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActionType::Nothing =>      { "nothing" }
+            ActionType::Error(_) =>     { "conflict" }
+            ActionType::DelLocal =>     { "delete_local" }
+            ActionType::DelRemote =>    { "delete_remote" }
+            ActionType::Local2Remote => { "upload" }
+            ActionType::Remote2Local => { "download" }
+        }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Hash, Clone)]
+#[derive(PartialEq, Eq, Debug, Hash, Clone, Deserialize, Serialize)]
 pub enum ErrorType{
     TwoSideMod,
     ModAndDel,
@@ -52,6 +65,51 @@ impl fmt::Display for ErrorType {
     }
 }
 
+/// An action that could not be applied and the reason why
+#[derive(Debug, Clone)]
+pub struct FailedAction {
+    pub action: Action,
+    pub error: String
+}
+
+/// Outcome of applying a list of actions
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    pub applied: Vec<Action>,
+    pub failed: Vec<FailedAction>,
+    /// Conflicts left unresolved, which were not applied
+    pub deferred: Vec<Action>,
+    /// Actions not applied because the sync was cancelled
+    pub undone: Vec<Action>
+}
+
+impl SyncReport {
+    pub fn is_success(&self) -> bool {
+        return self.failed.is_empty() && self.undone.is_empty();
+    }
+}
+
+/// Side to keep when adopting a file that exists on both sides with different content
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Preference {
+    Local,
+    Remote,
+    Newest
+}
+
+impl Preference {
+    fn resolve(&self, local: &RFileInfo, remote: &RFileInfo) -> ActionType {
+        match self {
+            Preference::Local =>  { ActionType::Local2Remote }
+            Preference::Remote => { ActionType::Remote2Local }
+            Preference::Newest => {
+                if local.mod_time.cmp(&remote.mod_time).is_ge() { ActionType::Local2Remote }
+                else { ActionType::Remote2Local }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Action {
     pub action: ActionType,
@@ -59,14 +117,34 @@ pub struct Action {
 }
 
 impl Action {
-    pub fn new(path: &String, action_type: ActionType) -> Self{
+    pub fn new(path: &str, action_type: ActionType) -> Self{
         return Action{
-            path: path.clone(),
+            path: path.to_string(),
             action: action_type
         }
     }
 
-    fn from(path: &String, local: &Option<OffsetDateTime>, remote: &Option<OffsetDateTime>, ledger: &Ledger) -> Self {
+    /// Resolves a conflicting action by keeping the local version of the file
+    pub fn keep_local(&mut self) {
+        // if the local side was a deletion, the deletion needs to be propagated to the remote
+        self.action = if matches!(self.action, ActionType::Error(ErrorType::DelAndMod)) {
+            ActionType::DelRemote
+        } else { // otherwise, the local has a file and it needs to be copied to the remote
+            ActionType::Local2Remote
+        };
+    }
+
+    /// Resolves a conflicting action by keeping the remote version of the file
+    pub fn keep_remote(&mut self) {
+        // if the remote side was a deletion, the deletion needs to be propagated to the local
+        self.action = if matches!(self.action, ActionType::Error(ErrorType::ModAndDel)) {
+            ActionType::DelLocal
+        } else { // otherwise, the remote has a file and it needs to be copied to the local
+            ActionType::Remote2Local
+        };
+    }
+
+    fn from(path: &str, local: &Option<OffsetDateTime>, remote: &Option<OffsetDateTime>, ledger: &Ledger) -> Self {
         match ledger.path_map.get(path) {
             // The ledger does not hold the file. This means it's new and should be added to the right destination
             // unless there are conflicting new files
@@ -139,14 +217,50 @@ impl Action {
     }
 }
 
-pub fn gen_action_list(local: &Vec<RFileInfo>, remote: &Vec<RFileInfo>, ledger: &Ledger) -> Vec<Action> {
+/// Compares both sides with the ledger. Conflicts recorded in the ledger are kept as they are until
+/// resolved, whatever happened to their files since
+pub fn gen_action_list(local: &[RFileInfo], remote: &[RFileInfo], ledger: &Ledger) -> Vec<Action> {
     let file_map = create_file_map(local, remote);
-    return file_map.iter().map(
-        |(p, times)| Action::from(p ,&times[0], &times[1], &ledger)
-    ).collect::<Vec<Action>>()
+    return file_map.iter().map(|(p, times)| match ledger.conflicts.get(p) {
+        Some(info) => Action::new(p, ActionType::Error(info.conflict.clone())),
+        None => Action::from(p ,&times[0], &times[1], ledger)
+    }).collect::<Vec<Action>>()
+}
+
+/// Generates the actions needed to adopt two pre-populated sides without a ledger. Files that are
+/// identical on both sides are recorded directly in the returned ledger and need no transfer.
+/// Differing files are resolved with the `prefer` strategy or left as `ErrorType::TwoNew` if none is given
+pub fn gen_adoption_list(local: &[RFileInfo], remote: &[RFileInfo], prefer: Option<Preference>) -> (Vec<Action>, Ledger) {
+    let update_time = OffsetDateTime::now_utc();
+    let mut ledger = Ledger::new();
+
+    let mut map: HashMap<&String, [Option<&RFileInfo>; 2]> = HashMap::new();
+    for (i, v) in Iterator::chain(local.iter().map(|v| (0, v)), remote.iter().map(|v| (1, v))) {
+        // filters out folders
+        if v.is_dir { continue; }
+        map.entry(&v.path).or_insert([None, None])[i] = Some(v);
+    }
+
+    let actions = map.iter().map(|(path, files)| {
+        match files {
+            [Some(l), Some(r)] if l.same_content(r) => {
+                ledger.path_map.insert(path.to_string(), LedgerInfo::new(update_time));
+                Action::new(path, ActionType::Nothing)
+            }
+            [Some(l), Some(r)] => match prefer {
+                None => Action::new(path, ActionType::Error(ErrorType::TwoNew)),
+                Some(prefer) => Action::new(path, prefer.resolve(l, r))
+            }
+            [Some(_), None] => Action::new(path, ActionType::Local2Remote),
+            [None, Some(_)] => Action::new(path, ActionType::Remote2Local),
+            [None, None] => panic!("Impossible scenario where a file exists but appears nowhere: \"{path}\"")
+        }
+    }).collect::<Vec<Action>>();
+
+    return (actions, ledger)
 }
 
-fn create_file_map(l1: &Vec<RFileInfo>, l2: &Vec<RFileInfo>) -> HashMap<String, [Option<OffsetDateTime>; 2]> {
+fn create_file_map(l1: &[RFileInfo], l2: &[RFileInfo]) -> HashMap<String, [Option<OffsetDateTime>; 2]> {
     let list_chain = Iterator::chain(
         l1.iter().map(|v| (0, v)),
         l2.iter().map(|v| (1, v))
@@ -157,9 +271,117 @@ fn create_file_map(l1: &Vec<RFileInfo>, l2: &Vec<RFileInfo>) -> HashMap<String,
         // filters out folders
         if v.is_dir { continue; }
 
-        let mut pair: [Option<OffsetDateTime>; 2] = map.get_mut(&v.path).unwrap_or(&mut [None, None]).clone();
+        let mut pair: [Option<OffsetDateTime>; 2] = *map.get(&v.path).unwrap_or(&[None, None]);
         pair[i] = Some(v.mod_time);
         map.insert(v.path.clone(), pair);
     }
     return map;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::ledger::ConflictInfo;
+
+    fn at(secs: i64) -> OffsetDateTime {
+        return OffsetDateTime::UNIX_EPOCH + Duration::seconds(secs);
+    }
+
+    fn action_of(actions: &[Action], path: &str) -> ActionType {
+        return actions.iter().find(|a| a.path == path).map(|a| a.action.clone()).unwrap();
+    }
+
+    /// Ledger synced at `at(10)` holding `paths`
+    fn ledger_of(paths: &[&str]) -> Ledger {
+        let mut ledger = Ledger::new();
+        for path in paths { ledger.path_map.insert(path.to_string(), LedgerInfo::new(at(10))); }
+        return ledger;
+    }
+
+    #[test]
+    fn actions_of_new_files() {
+        let (local, remote) = (MemoryBackend::new(), MemoryBackend::new());
+        local.insert("dir/local.txt", b"local", at(20));
+        remote.insert("remote.txt", b"remote", at(20));
+        local.insert("both.txt", b"local", at(20));
+        remote.insert("both.txt", b"remote", at(20));
+
+        let actions = gen_action_list(&local.list(false).unwrap(), &remote.list(false).unwrap(), &Ledger::new());
+
+        // directories get no action
+        assert_eq!(actions.len(), 3);
+        assert_eq!(action_of(&actions, "dir/local.txt"), ActionType::Local2Remote);
+        assert_eq!(action_of(&actions, "remote.txt"), ActionType::Remote2Local);
+        assert_eq!(action_of(&actions, "both.txt"), ActionType::Error(ErrorType::TwoNew));
+    }
+
+    #[test]
+    fn actions_of_known_files() {
+        let (local, remote) = (MemoryBackend::new(), MemoryBackend::new());
+        let ledger = ledger_of(&["same", "local_mod", "remote_mod", "both_mod", "local_del", "remote_del", "del_and_mod", "mod_and_del"]);
+        for (path, local_time, remote_time) in [
+            ("same", Some(5), Some(10)),
+            ("local_mod", Some(20), Some(10)),
+            ("remote_mod", Some(10), Some(20)),
+            ("both_mod", Some(20), Some(20)),
+            ("local_del", None, Some(10)),
+            ("remote_del", Some(10), None),
+            ("del_and_mod", None, Some(20)),
+            ("mod_and_del", Some(20), None)
+        ] {
+            if let Some(time) = local_time { local.insert(path, b"content", at(time)); }
+            if let Some(time) = remote_time { remote.insert(path, b"content", at(time)); }
+        }
+
+        let actions = gen_action_list(&local.list(false).unwrap(), &remote.list(false).unwrap(), &ledger);
+
+        assert_eq!(action_of(&actions, "same"), ActionType::Nothing);
+        assert_eq!(action_of(&actions, "local_mod"), ActionType::Local2Remote);
+        assert_eq!(action_of(&actions, "remote_mod"), ActionType::Remote2Local);
+        assert_eq!(action_of(&actions, "both_mod"), ActionType::Error(ErrorType::TwoSideMod));
+        assert_eq!(action_of(&actions, "local_del"), ActionType::DelRemote);
+        assert_eq!(action_of(&actions, "remote_del"), ActionType::DelLocal);
+        assert_eq!(action_of(&actions, "del_and_mod"), ActionType::Error(ErrorType::DelAndMod));
+        assert_eq!(action_of(&actions, "mod_and_del"), ActionType::Error(ErrorType::ModAndDel));
+    }
+
+    #[test]
+    fn adoption_records_identical_files() {
+        let (local, remote) = (MemoryBackend::new(), MemoryBackend::new());
+        local.insert("same.txt", b"same", at(20));
+        remote.insert("same.txt", b"same", at(30));
+        local.insert("differ.txt", b"local", at(30));
+        remote.insert("differ.txt", b"remote", at(20));
+        local.insert("local.txt", b"local", at(20));
+        remote.insert("remote.txt", b"remote", at(20));
+
+        let (local, remote) = (local.list(true).unwrap(), remote.list(true).unwrap());
+        let (actions, ledger) = gen_adoption_list(&local, &remote, None);
+
+        assert_eq!(action_of(&actions, "same.txt"), ActionType::Nothing);
+        assert_eq!(action_of(&actions, "differ.txt"), ActionType::Error(ErrorType::TwoNew));
+        assert_eq!(action_of(&actions, "local.txt"), ActionType::Local2Remote);
+        assert_eq!(action_of(&actions, "remote.txt"), ActionType::Remote2Local);
+        assert_eq!(ledger.path_map.keys().collect::<Vec<&String>>(), vec!["same.txt"]);
+
+        let (actions, _) = gen_adoption_list(&local, &remote, Some(Preference::Newest));
+        assert_eq!(action_of(&actions, "differ.txt"), ActionType::Local2Remote);
+        let (actions, _) = gen_adoption_list(&local, &remote, Some(Preference::Remote));
+        assert_eq!(action_of(&actions, "differ.txt"), ActionType::Remote2Local);
+    }
+
+    #[test]
+    fn recorded_conflicts_are_kept() {
+        let (local, remote) = (MemoryBackend::new(), MemoryBackend::new());
+        let mut ledger = ledger_of(&["conflict"]);
+        ledger.conflicts.insert("conflict".to_string(), ConflictInfo{ conflict: ErrorType::TwoSideMod, since: at(10) });
+        // only the local side changed since the conflict was recorded
+        local.insert("conflict", b"local", at(20));
+        remote.insert("conflict", b"remote", at(5));
+
+        let actions = gen_action_list(&local.list(false).unwrap(), &remote.list(false).unwrap(), &ledger);
+
+        assert_eq!(action_of(&actions, "conflict"), ActionType::Error(ErrorType::TwoSideMod));
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+use std::{process, thread};
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use rayon::prelude::*;
+use crate::action::{Action, ActionType, FailedAction, SyncReport};
+use crate::backend::{Backend, CANCELLED, CopyError, TransferContext, verify_copy};
+use crate::event::SyncEvent;
+use crate::session::SyncOptions;
+
+static STATS_GROUP: AtomicUsize = AtomicUsize::new(0);
+
+/// Hashes of the files verified after their transfer
+type Verified = Vec<(String, HashMap<String, String>)>;
+
+fn sort_actions(actions: &[Action]) -> HashMap<ActionType, Vec<String>> {
+    let mut map: HashMap<ActionType, Vec<String>> = HashMap::new();
+    for action in actions {
+        match map.get_mut(&action.action) {
+            None => { map.insert(action.action.clone(), vec![action.path.clone()]); }
+            Some(list) => { list.push(action.path.clone()); }
+        }
+    }
+    return map;
+}
+
+fn batch_actions(actions: HashMap<ActionType, Vec<String>>, size: usize) -> HashMap<(ActionType, usize), Vec<String>> {
+    let mut new_map = HashMap::new();
+    actions.iter().for_each(|(k, v)|{
+        match size {
+            0 => { new_map.insert((k.clone(), 0), v.clone());  }
+            _ => {
+                v.chunks(size).enumerate().for_each(|(i, v)|{
+                    new_map.insert((k.clone(), i), Vec::from(v));
+                });
+            }
+        }
+    });
+
+    return new_map;
+}
+
+/// Applies the actions between `local` and `remote` in batches. Failing batches are retried
+/// following the retry policy of `options`, then split down to single files to find the ones failing. The
+/// files that could not be transferred are listed in the returned report instead of stopping the
+/// other batches. While the actions run, the transfer statistics of the backends are polled and
+/// sent to `pipe`. The batches run on the current rayon pool, which is the pool of the calling
+/// `SyncEngine`. Once the sync is cancelled, the remaining files are reported as undone. With
+/// `options.verify`, copied files are checked on both sides and copied again on mismatch
+pub(crate) fn apply_actions(local: &dyn Backend, remote: &dyn Backend, actions: &[Action], pipe: Option<Sender<SyncEvent>>, options: &SyncOptions) -> SyncReport {
+    let ctx = TransferContext{
+        // the stats group isolating the transfers of this call
+        group: format!("warp-{}-{}", process::id(), STATS_GROUP.fetch_add(1, Ordering::Relaxed)),
+        timeout: options.timeout,
+        cancel: options.cancel.clone()
+    };
+    let group = &ctx.group;
+    let done = AtomicBool::new(false);
+
+    let actions = sort_actions(actions);
+    let actions = batch_actions(actions, options.batch_size);
+    let failures = thread::scope(|s| {
+        if let Some(tx) = &pipe {
+            s.spawn(|| poll_stats(local, remote, group, tx, &done));
+        }
+
+        let failures = actions.par_iter()
+            .map(|((a, _), list)| execute(local, remote, a, list, &pipe, options, &ctx))
+            .flatten()
+            .collect::<Vec<FailedAction>>();
+        done.store(true, Ordering::Relaxed);
+        return failures;
+    });
+    local.clear_stats(group);
+    remote.clear_stats(group);
+
+    let failed_paths = failures.iter().map(|f| &f.action.path).collect::<HashSet<&String>>();
+    let applied = actions.iter()
+        .filter(|((a, _), _)| a != &ActionType::Nothing)
+        .flat_map(|((a, _), list)| list.iter().map(|path| Action::new(path, a.clone())))
+        .filter(|action| !failed_paths.contains(&action.path))
+        .collect::<Vec<Action>>();
+
+    let (undone, failed): (Vec<FailedAction>, Vec<FailedAction>) = failures.into_iter().partition(|f| f.error == CANCELLED);
+    let undone = undone.into_iter().map(|f| f.action).collect();
+    return SyncReport{ applied, failed, deferred: Vec::new(), undone };
+}
+
+/// Sends the statistics of the stats `group` to `tx` until `done` is set. The remote usually
+/// does the transfers, so its statistics are preferred
+fn poll_stats(local: &dyn Backend, remote: &dyn Backend, group: &str, tx: &Sender<SyncEvent>, done: &AtomicBool) {
+    loop {
+        // read before polling so the last statistics are always sent
+        let finished = done.load(Ordering::Relaxed);
+
+        let events = remote.stats(group).or_else(|| local.stats(group)).unwrap_or_default();
+        for event in events { let _ = tx.send(event); }
+
+        if finished { return; }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn execute(local: &dyn Backend, remote: &dyn Backend, a: &ActionType, list: &[String], pipe: &Option<Sender<SyncEvent>>, options: &SyncOptions, ctx: &TransferContext) -> Vec<FailedAction> {
+    if a == &ActionType::Nothing { return Vec::new(); }
+
+    // sending to pipe starting signal for files
+    if let Some(tx) = &pipe {
+        list.iter().for_each( |s| tx.send(SyncEvent::ActionStarted(Action::new(s, a.clone()))).expect("Error while sending update"))
+    }
+
+    let (failures, verified) = execute_batch(local, remote, a, list, options.retry.retries, options, ctx);
+
+    // sending to pipe ending signal for files
+    if let Some(tx) = &pipe {
+        verified.into_iter()
+            .for_each(|(path, hashes)| tx.send(SyncEvent::ActionVerified{ path, hashes }).expect("Error while sending update"));
+        list.iter()
+            .filter(|s| !failures.iter().any(|f| &&f.action.path == s))
+            .for_each( |s| tx.send(SyncEvent::ActionFinished(Action::new(s, a.clone()))).expect("Error while sending update"));
+        failures.iter()
+            .for_each( |f| tx.send(SyncEvent::ActionFailed(f.clone())).expect("Error while sending update"));
+    }
+
+    return failures;
+}
+
+/// Runs a batch with retries. If it still fails, the batch is split in two halves which are
+/// run again without retries until the failing files are isolated. When the batch is cancelled, the
+/// files it copied before are done and only the others fail
+fn execute_batch(local: &dyn Backend, remote: &dyn Backend, a: &ActionType, list: &[String], retries: usize, options: &SyncOptions, ctx: &TransferContext) -> (Vec<FailedAction>, Verified) {
+    let retry = &options.retry;
+    let mut attempt = 0;
+    let error = loop {
+        match run(local, remote, a, list, options.verify, ctx) {
+            Ok(verified) => { return (Vec::new(), verified); }
+            Err(e) if e.error.kind() == ErrorKind::Interrupted => {
+                let failures = list.iter()
+                    .filter(|path| !e.copied.contains(path))
+                    .map(|path| FailedAction{ action: Action::new(path, a.clone()), error: e.error.to_string() })
+                    .collect();
+                return (failures, Vec::new());
+            }
+            Err(e) if attempt >= retries => { break e.error; }
+            Err(_) => {
+                thread::sleep(retry.delay * 2u32.saturating_pow(attempt as u32));
+                attempt += 1;
+            }
+        }
+    };
+
+    if list.len() == 1 {
+        return (vec![FailedAction{ action: Action::new(&list[0], a.clone()), error: error.to_string() }], Vec::new());
+    }
+
+    let (first, second) = list.split_at(list.len() / 2);
+    let (mut failures, mut verified) = execute_batch(local, remote, a, first, 0, options, ctx);
+    let (second_failures, second_verified) = execute_batch(local, remote, a, second, 0, options, ctx);
+    failures.extend(second_failures);
+    verified.extend(second_verified);
+    return (failures, verified);
+}
+
+fn run(local: &dyn Backend, remote: &dyn Backend, a: &ActionType, list: &[String], verify: bool, ctx: &TransferContext) -> Result<Verified, CopyError> {
+    ctx.check()?;
+    return match a {
+        ActionType::DelLocal =>     { Ok(local.delete(list, ctx).map(|_| Vec::new())?) }
+        ActionType::DelRemote =>    { Ok(remote.delete(list, ctx).map(|_| Vec::new())?) }
+        ActionType::Local2Remote => { transfer(local, remote, list, verify, ctx) }
+        ActionType::Remote2Local => { transfer(remote, local, list, verify, ctx) }
+        _ => { Err(Error::other(format!("An unexpected ActionType found during resolution ({a}). ")).into()) }
+    };
+}
+
+/// Copies the files from `from` to `to`, then verifies them. Once verified, every file is copied
+fn transfer(from: &dyn Backend, to: &dyn Backend, list: &[String], verify: bool, ctx: &TransferContext) -> Result<Verified, CopyError> {
+    from.copy(list, to, ctx)?;
+    return verify_all(from, to, list, verify, ctx).map_err(|error| CopyError{ copied: list.to_vec(), error });
+}
+
+/// Checks the files copied from `from` to `to`. A mismatch fails the batch, so it is copied again
+fn verify_all(from: &dyn Backend, to: &dyn Backend, list: &[String], verify: bool, ctx: &TransferContext) -> Result<Verified, Error> {
+    if !verify { return Ok(Vec::new()); }
+
+    return list.iter().map(|path| {
+        ctx.check()?;
+        return Ok((path.clone(), verify_copy(from, to, path)?));
+    }).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::ThreadPoolBuilder;
+    use time::OffsetDateTime;
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::engine::{CancelToken, RetryPolicy};
+    use crate::test_utils::FailingBackend;
+
+    fn local_of(paths: &[&str]) -> MemoryBackend {
+        let local = MemoryBackend::new();
+        for path in paths { local.insert(path, path.as_bytes(), OffsetDateTime::UNIX_EPOCH); }
+        return local;
+    }
+
+    fn uploads(paths: &[&str]) -> Vec<Action> {
+        return paths.iter().map(|path| Action::new(path, ActionType::Local2Remote)).collect();
+    }
+
+    fn options(batch_size: usize) -> SyncOptions {
+        return SyncOptions{ batch_size, retry: RetryPolicy{ retries: 1, delay: Duration::ZERO }, ..SyncOptions::default() };
+    }
+
+    const PATHS: [&str; 5] = ["a", "b", "c", "d", "e"];
+
+    #[test]
+    fn retry_recovers_transient_failures() {
+        let local = local_of(&PATHS);
+        let remote = FailingBackend::failing(&[("c", 1)]);
+
+        let report = apply_actions(&local, &remote, &uploads(&PATHS), None, &options(0));
+
+        assert!(report.is_success());
+        assert_eq!(report.applied.len(), 5);
+        assert_eq!(remote.inner.content("c").unwrap(), b"c");
+    }
+
+    #[test]
+    fn split_isolates_the_failing_file() {
+        let local = local_of(&PATHS);
+        let remote = FailingBackend::failing(&[("c", usize::MAX)]);
+
+        let report = apply_actions(&local, &remote, &uploads(&PATHS), None, &options(0));
+
+        assert_eq!(report.failed.iter().map(|f| f.action.path.as_str()).collect::<Vec<&str>>(), vec!["c"]);
+        assert!(report.undone.is_empty());
+        let mut applied = report.applied.iter().map(|a| a.path.as_str()).collect::<Vec<&str>>();
+        applied.sort();
+        assert_eq!(applied, vec!["a", "b", "d", "e"]);
+        for path in applied { assert_eq!(remote.inner.content(path).unwrap(), path.as_bytes()); }
+    }
+
+    #[test]
+    fn cancelled_actions_are_undone() {
+        let local = local_of(&PATHS);
+        let cancel = CancelToken::new();
+        let remote = FailingBackend{ cancel: Some(cancel.clone()), ..FailingBackend::default() };
+        let options = SyncOptions{ cancel, ..options(1) };
+
+        // a single worker runs the batches one after the other
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let report = pool.install(|| apply_actions(&local, &remote, &uploads(&PATHS), None, &options));
+
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.undone.len(), 4);
+        assert!(report.failed.is_empty());
+        assert_eq!(remote.inner.list(false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn files_copied_before_the_cancellation_are_applied() {
+        let local = local_of(&PATHS);
+        let cancel = CancelToken::new();
+        let remote = FailingBackend{ cancel: Some(cancel.clone()), ..FailingBackend::default() };
+        // a single batch, cancelled once its first file is written
+        let options = SyncOptions{ cancel, ..options(0) };
+
+        let report = apply_actions(&local, &remote, &uploads(&PATHS), None, &options);
+
+        assert_eq!(report.applied.iter().map(|a| a.path.as_str()).collect::<Vec<&str>>(), vec!["a"]);
+        assert_eq!(report.undone.len(), 4);
+        assert!(report.failed.is_empty());
+        assert_eq!(remote.inner.content("a").unwrap(), b"a");
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, Metadata};
+use std::io::{Error, ErrorKind, Read};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use rayon::prelude::*;
+use time::OffsetDateTime;
+use crate::backend::{Backend, RFileInfo, TransferContext, hash_content};
+
+/// Folder on the local filesystem, accessed natively. Listings include the inode and device of
+/// every entry on Unix
+pub struct LocalBackend {
+    root: PathBuf
+}
+
+impl LocalBackend {
+    pub fn new(root: &str) -> Self {
+        return LocalBackend{ root: PathBuf::from(root) }
+    }
+
+    /// Lists a directory and its subdirectories. Subdirectories, and the hashes of the files, are
+    /// handled in parallel on the current rayon pool
+    pub(crate) fn walk(&self, dir: &str, hashes: bool) -> Result<Vec<RFileInfo>, Error> {
+        let mut list = Vec::new();
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for entry in fs::read_dir(self.root.join(dir))? {
+            let entry = entry?;
+            // like rclone, symlinks and non UTF-8 names are skipped
+            let Ok(meta) = entry.metadata() else { continue; };
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue; };
+
+            let path = join(dir, &name);
+            if meta.is_dir() {
+                list.push(file_info(&path, &meta, HashMap::new()));
+                dirs.push(path);
+            }
+            else if meta.is_file() { files.push((path, meta)); }
+        }
+
+        let files = files.par_iter()
+            .map(|(path, meta)| {
+                let hashes = if hashes { self.hash(path)? } else { HashMap::new() };
+                return Ok(file_info(path, meta, hashes));
+            })
+            .collect::<Result<Vec<RFileInfo>, Error>>()?;
+        list.extend(files);
+
+        let sub_lists = dirs.par_iter()
+            .map(|path| self.walk(path, hashes))
+            .collect::<Result<Vec<Vec<RFileInfo>>, Error>>()?;
+        list.extend(sub_lists.into_iter().flatten());
+        return Ok(list);
+    }
+}
+
+impl Backend for LocalBackend {
+    fn fs(&self) -> Option<String> {
+        return self.root.to_str().map(str::to_string);
+    }
+
+    fn list(&self, hashes: bool) -> Result<Vec<RFileInfo>, Error> {
+        return self.walk("", hashes);
+    }
+
+    fn stat(&self, path: &str) -> Result<Option<RFileInfo>, Error> {
+        return match fs::symlink_metadata(self.root.join(path)) {
+            Ok(meta) if meta.is_file() || meta.is_dir() => Ok(Some(file_info(path, &meta, HashMap::new()))),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+        };
+    }
+
+    fn hash(&self, path: &str) -> Result<HashMap<String, String>, Error> {
+        return hash_content(&mut File::open(self.root.join(path))?);
+    }
+
+    fn read(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        return Ok(Box::new(File::open(self.root.join(path))?));
+    }
+
+    fn write(&self, path: &str, content: &mut dyn Read, mod_time: OffsetDateTime) -> Result<(), Error> {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+
+        let mut file = File::create(&path)?;
+        std::io::copy(content, &mut file)?;
+        file.set_modified(mod_time.into())?;
+        return Ok(());
+    }
+
+    fn delete(&self, files: &[String], _ctx: &TransferContext) -> Result<(), Error> {
+        for path in files {
+            match fs::remove_file(self.root.join(path)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => { return Err(e); }
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+
+    fn move_file(&self, from: &str, to: &str) -> Result<(), Error> {
+        let to = self.root.join(to);
+        if let Some(parent) = to.parent() { fs::create_dir_all(parent)?; }
+        return fs::rename(self.root.join(from), to);
+    }
+}
+
+/// Information about a file of the local filesystem. Directories are listed with a size of -1 like rclone does
+pub(crate) fn file_info(path: &str, meta: &Metadata, hashes: HashMap<String, String>) -> RFileInfo {
+    #[cfg(unix)]
+    let (inode, device) = (Some(meta.ino()), Some(meta.dev()));
+    #[cfg(not(unix))]
+    let (inode, device) = (None, None);
+
+    return RFileInfo{
+        path: path.to_string(),
+        size: if meta.is_dir() { -1 } else { meta.len() as i64 },
+        mod_time: meta.modified().map(OffsetDateTime::from).unwrap_or(OffsetDateTime::UNIX_EPOCH),
+        is_dir: meta.is_dir(),
+        hashes,
+        inode,
+        device
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    return if dir.is_empty() { name.to_string() } else { format!("{dir}/{name}") };
+}
@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use crate::backend::{Backend, RFileInfo, TransferContext, hash_content};
+
+struct MemoryFile {
+    content: Vec<u8>,
+    mod_time: OffsetDateTime
+}
+
+/// Backend holding its files in memory. Directories only exist through the files they contain.
+/// Mostly useful to run syncs without touching any real storage
+#[derive(Default)]
+pub struct MemoryBackend {
+    files: Mutex<BTreeMap<String, MemoryFile>>
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        return MemoryBackend::default();
+    }
+
+    /// Adds or replaces a file
+    pub fn insert(&self, path: &str, content: &[u8], mod_time: OffsetDateTime) {
+        self.files.lock().unwrap().insert(path.to_string(), MemoryFile{ content: content.to_vec(), mod_time });
+    }
+
+    /// Content of a file, or `None` if it does not exist
+    pub fn content(&self, path: &str) -> Option<Vec<u8>> {
+        return self.files.lock().unwrap().get(path).map(|f| f.content.clone());
+    }
+
+    fn file_info(path: &str, file: &MemoryFile, hashes: bool) -> Result<RFileInfo, Error> {
+        return Ok(RFileInfo{
+            path: path.to_string(),
+            size: file.content.len() as i64,
+            mod_time: file.mod_time,
+            is_dir: false,
+            hashes: if hashes { hash_content(&mut file.content.as_slice())? } else { HashMap::new() },
+            inode: None,
+            device: None
+        });
+    }
+
+    fn dir_info(path: &str) -> RFileInfo {
+        return RFileInfo{
+            path: path.to_string(), size: -1, mod_time: OffsetDateTime::UNIX_EPOCH, is_dir: true,
+            hashes: HashMap::new(), inode: None, device: None
+        };
+    }
+
+    fn not_found(path: &str) -> Error {
+        return Error::new(ErrorKind::NotFound, format!("file not found: {path}"));
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn list(&self, hashes: bool) -> Result<Vec<RFileInfo>, Error> {
+        let files = self.files.lock().unwrap();
+
+        let mut dirs = BTreeSet::new();
+        for path in files.keys() {
+            let mut parent = path.as_str();
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                dirs.insert(dir);
+                parent = dir;
+            }
+        }
+
+        let mut list = dirs.into_iter().map(Self::dir_info).collect::<Vec<RFileInfo>>();
+        for (path, file) in files.iter() {
+            list.push(Self::file_info(path, file, hashes)?);
+        }
+        return Ok(list);
+    }
+
+    fn stat(&self, path: &str) -> Result<Option<RFileInfo>, Error> {
+        let files = self.files.lock().unwrap();
+        if let Some(file) = files.get(path) {
+            return Self::file_info(path, file, false).map(Some);
+        }
+
+        let prefix = format!("{path}/");
+        return Ok(files.keys().any(|p| p.starts_with(&prefix)).then(|| Self::dir_info(path)));
+    }
+
+    fn hash(&self, path: &str) -> Result<HashMap<String, String>, Error> {
+        let files = self.files.lock().unwrap();
+        let file = files.get(path).ok_or_else(|| Self::not_found(path))?;
+        return hash_content(&mut file.content.as_slice());
+    }
+
+    fn read(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let content = self.content(path).ok_or_else(|| Self::not_found(path))?;
+        return Ok(Box::new(Cursor::new(content)));
+    }
+
+    fn write(&self, path: &str, content: &mut dyn Read, mod_time: OffsetDateTime) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        content.read_to_end(&mut buffer)?;
+        self.files.lock().unwrap().insert(path.to_string(), MemoryFile{ content: buffer, mod_time });
+        return Ok(());
+    }
+
+    fn delete(&self, files: &[String], _ctx: &TransferContext) -> Result<(), Error> {
+        let mut map = self.files.lock().unwrap();
+        files.iter().for_each(|path| { map.remove(path); });
+        return Ok(());
+    }
+
+    fn move_file(&self, from: &str, to: &str) -> Result<(), Error> {
+        let mut files = self.files.lock().unwrap();
+        let file = files.remove(from).ok_or_else(|| Self::not_found(from))?;
+        files.insert(to.to_string(), file);
+        return Ok(());
+    }
+}
@@ -0,0 +1,250 @@
+mod local;
+mod memory;
+
+pub use local::LocalBackend;
+pub use memory::MemoryBackend;
+pub(crate) use local::file_info;
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read};
+use std::time::Duration;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use time::OffsetDateTime;
+use crate::engine::CancelToken;
+use crate::event::SyncEvent;
+
+/// A file or directory of a backend, in the format of rclone listings
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RFileInfo {
+    #[serde(rename = "Path")]
+    pub path: String,
+    //#[serde(rename = "Name")]
+    //pub name: String,
+    #[serde(rename = "Size")]
+    pub size: i64,
+    // pub MimeType: String,
+    #[serde(rename = "ModTime", with = "time::serde::rfc3339")]
+    pub mod_time: OffsetDateTime,
+    #[serde(rename = "IsDir")]
+    pub is_dir: bool,
+    // pub ID: String,
+    #[serde(rename = "Hashes", default)]
+    pub hashes: HashMap<String, String>,
+    /// Inode of the file, for backends that know it
+    #[serde(rename = "Inode", default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<u64>,
+    /// Device holding the file, for backends that know it
+    #[serde(rename = "Device", default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<u64>,
+}
+
+impl RFileInfo {
+    /// Checks if two files hold the same content. The sizes must match and so must every hash type
+    /// available on both sides. If no hash type is shared, the modification times are compared instead
+    pub fn same_content(&self, other: &RFileInfo) -> bool {
+        if self.size != other.size { return false; }
+
+        let mut shared = self.hashes.iter().filter_map(|(kind, hash)| {
+            other.hashes.get(kind).map(|other_hash| hash.eq_ignore_ascii_case(other_hash))
+        }).peekable();
+
+        if shared.peek().is_none() {
+            let precision = precision_of(self.mod_time).max(precision_of(other.mod_time));
+            return truncate_to(self.mod_time, precision) == truncate_to(other.mod_time, precision);
+        }
+        return shared.all(|same| same);
+    }
+
+    /// Checks if both entries are the same file on disk, which stays true when a file is renamed.
+    /// Always false when the inodes are unknown
+    pub fn same_file(&self, other: &RFileInfo) -> bool {
+        return self.inode.is_some() && self.device.is_some() && self.inode == other.inode && self.device == other.device;
+    }
+}
+
+/// Precision in nanoseconds a timestamp was stored with, as backends keep mtimes from seconds to nanoseconds
+fn precision_of(time: OffsetDateTime) -> i128 {
+    let nanos = time.nanosecond() as i128;
+    for precision in [1_000_000_000, 1_000_000, 1_000] {
+        if nanos % precision == 0 { return precision; }
+    }
+    return 1;
+}
+
+fn truncate_to(time: OffsetDateTime, precision: i128) -> i128 {
+    let nanos = time.unix_timestamp_nanos();
+    return nanos - nanos.rem_euclid(precision);
+}
+
+/// Error of the operations stopped because the sync is cancelled
+pub(crate) const CANCELLED: &str = "sync cancelled";
+
+/// Context of the transfers of a sync, shared by all its batches
+#[derive(Debug, Clone)]
+pub struct TransferContext {
+    /// Identifies the transfers of the sync, see `Backend::stats`
+    pub group: String,
+    /// Maximum duration of a single operation, for backends able to stop them
+    pub timeout: Option<Duration>,
+    pub cancel: CancelToken
+}
+
+impl TransferContext {
+    /// Fails with `ErrorKind::Interrupted` if the sync is cancelled
+    pub fn check(&self) -> Result<(), Error> {
+        return match self.cancel.is_cancelled() {
+            true => Err(Error::new(ErrorKind::Interrupted, CANCELLED)),
+            false => Ok(())
+        };
+    }
+}
+
+/// Error of a copy stopped partway. The files listed in `copied` were fully copied before it stopped
+#[derive(Debug)]
+pub struct CopyError {
+    pub copied: Vec<String>,
+    pub error: Error
+}
+
+impl From<Error> for CopyError {
+    fn from(error: Error) -> Self {
+        return CopyError{ copied: Vec::new(), error };
+    }
+}
+
+/// Storage holding one side of a link. Paths are relative to the root of the backend and use '/'
+/// as separator, whatever the platform
+pub trait Backend: Send + Sync {
+    /// Name of the backend as understood by rclone, if rclone can access it. Transfers between
+    /// two such backends are done by rclone
+    fn fs(&self) -> Option<String> { None }
+
+    /// Lists every file and directory recursively. With `hashes`, the hashes of the files are
+    /// listed too
+    fn list(&self, hashes: bool) -> Result<Vec<RFileInfo>, Error>;
+
+    /// Information about a single file, or `None` if it does not exist
+    fn stat(&self, path: &str) -> Result<Option<RFileInfo>, Error>;
+
+    /// Hashes of a file by hash type, named as rclone names them
+    fn hash(&self, path: &str) -> Result<HashMap<String, String>, Error>;
+
+    fn read(&self, path: &str) -> Result<Box<dyn Read + Send>, Error>;
+
+    /// Writes a file, creating its parent directories if needed, and sets its modification time
+    fn write(&self, path: &str, content: &mut dyn Read, mod_time: OffsetDateTime) -> Result<(), Error>;
+
+    /// Deletes the files. Files that do not exist are ignored
+    fn delete(&self, files: &[String], ctx: &TransferContext) -> Result<(), Error>;
+
+    /// Moves a file inside the backend
+    fn move_file(&self, from: &str, to: &str) -> Result<(), Error>;
+
+    /// Copies files between two rclone names with the rclone used by this backend, if it uses one
+    fn rclone_copy(&self, _from: &str, _to: &str, _files: &[String], _ctx: &TransferContext) -> Option<Result<(), CopyError>> { None }
+
+    /// Copies the files to `dest`, keeping their paths and modification times. Files are copied by
+    /// rclone when both backends have an rclone name and one of them uses rclone, otherwise they
+    /// are streamed through `read` and `write`. On failure, the error tells which files were copied
+    fn copy(&self, files: &[String], dest: &dyn Backend, ctx: &TransferContext) -> Result<(), CopyError> {
+        if let (Some(from), Some(to)) = (self.fs(), dest.fs()) {
+            let copied = dest.rclone_copy(&from, &to, files, ctx)
+                .or_else(|| self.rclone_copy(&from, &to, files, ctx));
+            if let Some(res) = copied { return res; }
+        }
+
+        let mut copied = Vec::new();
+        for path in files {
+            let res = ctx.check().and_then(|_| {
+                let mod_time = self.stat(path)?.map(|f| f.mod_time).unwrap_or(OffsetDateTime::now_utc());
+                return dest.write(path, &mut self.read(path)?, mod_time);
+            });
+            if let Err(error) = res { return Err(CopyError{ copied, error }); }
+            copied.push(path.clone());
+        }
+        return Ok(());
+    }
+
+    /// Progress of the transfers of `group`, as `ActionProgress` and `Stats` events, if the
+    /// backend tracks it
+    fn stats(&self, _group: &str) -> Option<Vec<SyncEvent>> { None }
+
+    /// Forgets the statistics of `group` once its transfers are done
+    fn clear_stats(&self, _group: &str) {}
+}
+
+/// Checks that a file copied from `from` to `to` holds the same content on both sides and returns
+/// the hashes compared. Files are read back and hashed when the backends share no hash type
+pub(crate) fn verify_copy(from: &dyn Backend, to: &dyn Backend, path: &str) -> Result<HashMap<String, String>, Error> {
+    let shared = |source: &HashMap<String, String>, dest: &HashMap<String, String>| source.iter()
+        .filter(|(kind, _)| dest.contains_key(*kind))
+        .map(|(kind, hash)| (kind.clone(), hash.clone()))
+        .collect::<HashMap<String, String>>();
+
+    let (mut source, mut dest) = (from.hash(path)?, to.hash(path)?);
+    if shared(&source, &dest).is_empty() {
+        source = hash_content(&mut from.read(path)?)?;
+        dest = hash_content(&mut to.read(path)?)?;
+    }
+
+    let hashes = shared(&source, &dest);
+    if hashes.iter().any(|(kind, hash)| !hash.eq_ignore_ascii_case(&dest[kind])) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("checksum mismatch after the transfer of {path}")));
+    }
+    return Ok(hashes);
+}
+
+/// Computes the md5 and sha1 hashes of a content, which most rclone remotes support
+pub(crate) fn hash_content(content: &mut dyn Read) -> Result<HashMap<String, String>, Error> {
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = content.read(&mut buffer)?;
+        if read == 0 { break; }
+        md5.update(&buffer[..read]);
+        sha1.update(&buffer[..read]);
+    }
+
+    return Ok(HashMap::from([
+        ("md5".to_string(), format!("{:x}", md5.finalize())),
+        ("sha1".to_string(), format!("{:x}", sha1.finalize()))
+    ]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Remote without any hash type, like some rclone remotes
+    struct NoHashes(MemoryBackend);
+
+    impl Backend for NoHashes {
+        fn list(&self, _hashes: bool) -> Result<Vec<RFileInfo>, Error> { self.0.list(false) }
+        fn stat(&self, path: &str) -> Result<Option<RFileInfo>, Error> { self.0.stat(path) }
+        fn hash(&self, _path: &str) -> Result<HashMap<String, String>, Error> { Ok(HashMap::new()) }
+        fn read(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> { self.0.read(path) }
+        fn write(&self, path: &str, content: &mut dyn Read, mod_time: OffsetDateTime) -> Result<(), Error> { self.0.write(path, content, mod_time) }
+        fn delete(&self, files: &[String], ctx: &TransferContext) -> Result<(), Error> { self.0.delete(files, ctx) }
+        fn move_file(&self, from: &str, to: &str) -> Result<(), Error> { self.0.move_file(from, to) }
+    }
+
+    #[test]
+    fn verify_reads_both_sides_without_shared_hash() {
+        let local = MemoryBackend::new();
+        local.insert("same", b"content", OffsetDateTime::UNIX_EPOCH);
+        local.insert("changed", b"content", OffsetDateTime::UNIX_EPOCH);
+        let remote = NoHashes(MemoryBackend::new());
+        remote.0.insert("same", b"content", OffsetDateTime::UNIX_EPOCH);
+        // same size and modification time, only the content tells them apart
+        remote.0.insert("changed", b"corrupt", OffsetDateTime::UNIX_EPOCH);
+
+        let hashes = verify_copy(&local, &remote, "same").unwrap();
+        assert_eq!(hashes, hash_content(&mut "content".as_bytes()).unwrap());
+
+        let err = verify_copy(&local, &remote, "changed").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
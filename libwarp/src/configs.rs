@@ -1,9 +1,13 @@
 use std::collections::HashSet;
 use std::env;
 use std::path::{Path, PathBuf};
-use std::fs::{create_dir_all, File, read_dir};
-use std::io::{Error};
+use std::fs::{create_dir_all, File, OpenOptions, read_dir};
+use std::io::{Error, ErrorKind};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
 use serde::{Deserialize, Serialize};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
 
 fn get_config_path() -> PathBuf {
     #[allow(deprecated)]
@@ -17,7 +21,126 @@ pub struct Config {
     pub link_path: String,
     pub local: String,
     pub remote: String,
-    pub update_rt: usize
+    /// Interval in minutes between two syncs made by the daemon. If 0, the daemon's default is used
+    pub update_rt: usize,
+    /// rclone server reaching the remote. If not set, the rclone embedded in warp is used
+    #[serde(default)]
+    pub rcd: Option<RcdConfig>,
+    /// Encryption of the data on the remote. If not set, the remote holds plain files
+    #[serde(default)]
+    pub crypt: Option<CryptConfig>,
+    /// Checks the content of every transferred file on both sides, copying it again on mismatch
+    #[serde(default)]
+    pub verify: bool
+}
+
+/// Client-side encryption of a link. The remote is wrapped in an rclone crypt layer, so the
+/// remote only holds encrypted files while warp works on the plain ones
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CryptConfig {
+    pub salt: String,
+    /// If set, the password is a passphrase given on every sync. Otherwise it is generated and
+    /// kept in the key file of the link, readable by its owner only
+    pub passphrase: bool,
+    /// Argon2id hash of the password, to reject a wrong one before the remote is read. Stored in
+    /// the PHC string format, which holds the salt and the parameters of the hash
+    #[serde(default)]
+    pub check: Option<String>
+}
+
+/// Content of the key file of an encrypted link
+#[derive(Deserialize, Serialize)]
+struct CryptKey {
+    password: String
+}
+
+impl CryptConfig {
+    /// Generates the secrets of a new encrypted link protected by `passphrase`. Without one, a
+    /// password is generated and saved in the key file of the link
+    pub fn generate(link_path: &str, passphrase: Option<&str>) -> Result<Self, Error> {
+        let password = match passphrase {
+            Some(passphrase) => passphrase.to_string(),
+            None => {
+                let password = random_secret()?;
+                Self::save_password(link_path, &password)?;
+                password
+            }
+        };
+        let salt = random_secret()?;
+        let check = Some(check_of(&password)?);
+        return Ok(CryptConfig{ salt, passphrase: passphrase.is_some(), check });
+    }
+
+    /// Password of the crypt layer. `passphrase` is only used by links protected by one. A
+    /// password not matching the one the link was created with is rejected, as the remote would
+    /// look empty with it
+    pub fn password(&self, link_path: &str, passphrase: Option<&str>) -> Result<String, Error> {
+        let password = match self.passphrase {
+            true => passphrase.map(str::to_string)
+                .ok_or(Error::new(ErrorKind::PermissionDenied, "this link is encrypted with a passphrase, which was not given"))?,
+            false => {
+                let key: CryptKey = serde_json::from_reader(File::open(Path::new(link_path).join("crypt.key"))?)?;
+                key.password
+            }
+        };
+
+        let matches = match &self.check {
+            Some(check) => matches_check(check, &password)?,
+            None => true
+        };
+        if !matches {
+            let message = if self.passphrase { "wrong passphrase" } else { "the key file does not match the link" };
+            return Err(Error::new(ErrorKind::PermissionDenied, message));
+        }
+        return Ok(password);
+    }
+
+    fn save_password(link_path: &str, password: &str) -> Result<(), Error> {
+        create_dir_all(link_path)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let file = options.open(Path::new(link_path).join("crypt.key"))?;
+        serde_json::to_writer(file, &CryptKey{ password: password.to_string() })?;
+        return Ok(());
+    }
+}
+
+/// Argon2id hash of the password with a random salt, in the PHC string format
+fn check_of(password: &str) -> Result<String, Error> {
+    let mut salt = [0u8; 16];
+    getrandom::fill(&mut salt).map_err(|e| Error::other(e.to_string()))?;
+    let salt = SaltString::encode_b64(&salt).map_err(|e| Error::other(e.to_string()))?;
+    return Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::other(e.to_string()));
+}
+
+/// Checks the password against a hash made by `check_of`, with the parameters stored in the hash
+fn matches_check(check: &str, password: &str) -> Result<bool, Error> {
+    let hash = PasswordHash::new(check)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid password check in the link config: {e}")))?;
+    return Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok());
+}
+
+/// 32 random bytes, hex encoded
+fn random_secret() -> Result<String, Error> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|e| Error::other(e.to_string()))?;
+    return Ok(bytes.iter().map(|b| format!("{b:02x}")).collect());
+}
+
+/// External `rclone rcd` server, reached through its HTTP remote control API
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RcdConfig {
+    /// Address of the server (ex: http://localhost:5572)
+    pub url: String,
+    pub user: Option<String>,
+    /// Password of `user`. Never saved, it is given at run time
+    #[serde(default, skip_serializing)]
+    pub pass: Option<String>
 }
 
 impl Config {
@@ -38,6 +161,9 @@ impl Config {
             local: local_path.to_str().unwrap().to_string(),
             remote: remote.to_string(),
             update_rt,
+            rcd: None,
+            crypt: None,
+            verify: false
         }
     }
 
@@ -61,4 +187,46 @@ impl Config {
             &self
         ).expect("Error while saving link info");
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let link = TempDir::new();
+        let crypt = CryptConfig::generate(link.str(), Some("right")).unwrap();
+
+        assert_eq!(crypt.password(link.str(), Some("right")).unwrap(), "right");
+        assert_eq!(crypt.password(link.str(), Some("wrong")).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(crypt.password(link.str(), None).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(!Path::new(link.str()).join("crypt.key").exists());
+    }
+
+    #[test]
+    fn check_is_a_salted_argon2_hash() {
+        let link = TempDir::new();
+        let first = CryptConfig::generate(link.str(), Some("right")).unwrap().check.unwrap();
+        let second = CryptConfig::generate(link.str(), Some("right")).unwrap().check.unwrap();
+
+        assert!(first.starts_with("$argon2id$"));
+        assert!(!first.contains("right"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn key_file_must_match_the_link() {
+        let link = TempDir::new();
+        let crypt = CryptConfig::generate(link.str(), None).unwrap();
+        let password = crypt.password(link.str(), None).unwrap();
+
+        let other = TempDir::new();
+        CryptConfig::generate(other.str(), None).unwrap();
+        assert_eq!(crypt.password(other.str(), None).unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+        // links created before the check accept any password
+        let unchecked = CryptConfig{ check: None, ..crypt };
+        assert_eq!(unchecked.password(link.str(), None).unwrap(), password);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+use std::io::{BufRead, Write};
+use crate::action::{Action, ActionType, ErrorType, Preference};
+use crate::backend::RFileInfo;
+use crate::ledger::LedgerInfo;
+
+/// How a conflict is settled
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Resolution {
+    KeepLocal,
+    KeepRemote,
+    /// Keeps both versions by renaming the remote one. For conflicts involving a deletion, the
+    /// file that still exists is kept
+    KeepBoth,
+    /// Leaves the file untouched. The conflict is recorded and kept by the next syncs until resolved
+    Skip,
+    /// Stops the sync before anything is transferred
+    Abort
+}
+
+impl Resolution {
+    /// Stable name of the resolution, used by the sync history
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resolution::KeepLocal =>  { "keep_local" }
+            Resolution::KeepRemote => { "keep_remote" }
+            Resolution::KeepBoth =>   { "keep_both" }
+            Resolution::Skip =>       { "skip" }
+            Resolution::Abort =>      { "abort" }
+        }
+    }
+}
+
+/// Decides how the conflicts found during a sync are settled
+pub trait ConflictResolver {
+    /// Called for every conflicting action with the files of both sides, if they exist, and the
+    /// ledger entry of the file, if there is one
+    fn resolve(&mut self, action: &Action, local: Option<&RFileInfo>, remote: Option<&RFileInfo>, entry: Option<&LedgerInfo>) -> Resolution;
+}
+
+/// Resolves every conflict the same way
+pub struct StaticResolver(pub Resolution);
+
+impl ConflictResolver for StaticResolver {
+    fn resolve(&mut self, _: &Action, _: Option<&RFileInfo>, _: Option<&RFileInfo>, _: Option<&LedgerInfo>) -> Resolution {
+        return self.0;
+    }
+}
+
+/// Keeps the preferred side. With `Preference::Newest`, conflicts involving a deletion keep the
+/// modified file since the deletion time is unknown
+impl ConflictResolver for Preference {
+    fn resolve(&mut self, _: &Action, local: Option<&RFileInfo>, remote: Option<&RFileInfo>, _: Option<&LedgerInfo>) -> Resolution {
+        let keep_local = match (self, local, remote) {
+            (Preference::Local, _, _) => true,
+            (Preference::Remote, _, _) => false,
+            (Preference::Newest, Some(l), Some(r)) => l.mod_time.cmp(&r.mod_time).is_ge(),
+            (Preference::Newest, local, _) => local.is_some()
+        };
+        return if keep_local { Resolution::KeepLocal } else { Resolution::KeepRemote };
+    }
+}
+
+/// Asks the user on the terminal
+pub struct TerminalResolver;
+
+impl ConflictResolver for TerminalResolver {
+    fn resolve(&mut self, action: &Action, local: Option<&RFileInfo>, remote: Option<&RFileInfo>, _: Option<&LedgerInfo>) -> Resolution {
+        println!("\n{} in file: {}", action.action, action.path);
+        for (side, file) in [("local", local), ("remote", remote)] {
+            match file {
+                None => println!("    {side: <6}: deleted"),
+                Some(f) => println!("    {side: <6}: {} bytes, modified {}", f.size, f.mod_time)
+            }
+        }
+
+        loop {
+            print!("keep the REMOTE, LOCAL or BOTH, SKIP it or ABORT the sync? (r/l/b/s/a): ");
+            io::stdout().flush().expect("");
+
+            let mut input = String::new();
+            match io::stdin().lock().read_line(&mut input) {
+                // stdin is closed, nobody can answer
+                Ok(0) => { return Resolution::Skip; }
+                Err(_) => { continue; }
+                Ok(_) => {}
+            }
+
+            match input.trim().to_lowercase().as_str() {
+                "l" => { return Resolution::KeepLocal; }
+                "r" => { return Resolution::KeepRemote; }
+                "b" => { return Resolution::KeepBoth; }
+                "s" => { return Resolution::Skip; }
+                "a" => { return Resolution::Abort; }
+                _ => { continue; }
+            }
+        }
+    }
+}
+
+impl Action {
+    /// Checks if keeping both versions needs the remote file to be renamed, which is only the case
+    /// when the file exists on both sides
+    pub(crate) fn needs_rename(&self) -> bool {
+        return matches!(self.action, ActionType::Error(ErrorType::TwoSideMod) | ActionType::Error(ErrorType::TwoNew));
+    }
+}
+
+/// Name given to the remote version of a file kept with `Resolution::KeepBoth`
+pub(crate) fn conflict_name(path: &str, timestamp: i64) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{dir}/"), name),
+        None => (String::new(), path)
+    };
+    return match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{dir}{stem} (remote conflict {timestamp}).{ext}"),
+        _ => format!("{dir}{name} (remote conflict {timestamp})")
+    };
+}
@@ -0,0 +1,94 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::action::{Action, SyncReport};
+use crate::apply::apply_actions;
+use crate::backend::Backend;
+use crate::event::SyncEvent;
+use crate::session::SyncOptions;
+
+/// How failing batches are retried. The delay doubles after every attempt
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retries: usize,
+    pub delay: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        return RetryPolicy{ retries: 3, delay: Duration::from_secs(1) }
+    }
+}
+
+/// Stops running syncs when cancelled. Clones share the same state, so a token can be kept by the
+/// frontend while its clone is given to a sync
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        return CancelToken::default();
+    }
+
+    /// Stops the transfers in flight and skips the ones not started yet. The files not
+    /// transferred are reported as failed and found again by the next sync
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.0.load(Ordering::Relaxed);
+    }
+}
+
+/// Number of live engines. rclone is initialized with the first one and finalized with the last one
+static ENGINES: Mutex<usize> = Mutex::new(0);
+
+/// Runs syncs on its own pool of workers. An engine can run any number of syncs, of the same or of
+/// different links, one after the other or concurrently from different threads. rclone is
+/// initialized as long as an engine is alive
+pub struct SyncEngine {
+    pool: ThreadPool
+}
+
+impl SyncEngine {
+    pub fn new(thread_nb: usize) -> Self {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(thread_nb)
+            .thread_name(|i| format!("warp-worker-{i}"))
+            .build()
+            .expect("Unable to create the worker pool");
+
+        let mut engines = ENGINES.lock().unwrap();
+        #[cfg(feature = "rclone")]
+        if *engines == 0 { librclone::initialize(); }
+        *engines += 1;
+
+        return SyncEngine{ pool }
+    }
+
+    /// Applies the actions between `local` and `remote`. The actions are run in batches and failing
+    /// batches are retried, as set in `options`. The progress is sent to `pipe`
+    pub fn apply(&self, local: &dyn Backend, remote: &dyn Backend, actions: &[Action], pipe: Option<Sender<SyncEvent>>, options: &SyncOptions) -> SyncReport {
+        return self.pool.install(|| apply_actions(local, remote, actions, pipe, options));
+    }
+
+    /// Runs `f` on the pool of the engine, so the parallel work it does uses the same workers
+    pub(crate) fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        return self.pool.install(f);
+    }
+
+    /// Stops the workers once their current work is done. Same as dropping the engine
+    pub fn shutdown(self) {}
+}
+
+impl Drop for SyncEngine {
+    fn drop(&mut self) {
+        let mut engines = ENGINES.lock().unwrap();
+        *engines -= 1;
+        #[cfg(feature = "rclone")]
+        if *engines == 0 { librclone::finalize(); }
+    }
+}
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use crate::action::{Action, FailedAction};
+
+/// Side of a link
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Side {
+    Local,
+    Remote
+}
+
+/// Transfer statistics of a sync, as reported by rclone
+#[derive(Debug, Clone, Default)]
+pub struct TransferStats {
+    /// Bytes transferred so far
+    pub bytes: u64,
+    /// Bytes of the transfers rclone knows about so far
+    pub total_bytes: u64,
+    /// Average speed in bytes per second
+    pub speed: f64,
+    /// Estimated time left in seconds
+    pub eta: Option<u64>
+}
+
+/// Events sent while a sync is running
+#[derive(Debug, Clone)]
+pub enum SyncEvent {
+    ListingStarted(Side),
+    ListingFinished(Side, usize),
+    /// The actions are decided and about to be applied
+    Planned{ actions: usize, bytes: u64 },
+    ActionStarted(Action),
+    ActionProgress{ path: String, bytes: u64, size: u64 },
+    /// The hashes of a transferred file matched on both sides. Sent before its `ActionFinished`
+    ActionVerified{ path: String, hashes: HashMap<String, String> },
+    ActionFinished(Action),
+    ActionFailed(FailedAction),
+    Stats(TransferStats)
+}
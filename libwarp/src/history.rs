@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use crate::action::{Action, SyncReport};
+use crate::conflict::Resolution;
+
+/// Summary of a past sync of a link. The history of a link is kept in the `history.jsonl` file of
+/// its link folder, one entry per line
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct HistoryEntry {
+    #[serde(with = "time::serde::rfc3339")]
+    pub started: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub finished: OffsetDateTime,
+    /// Number of applied actions by action type name
+    pub counts: BTreeMap<String, usize>,
+    /// Size of the files copied
+    pub bytes: u64,
+    /// Actions applied
+    pub files: Vec<FileRecord>,
+    /// Actions that failed, with their error
+    pub errors: Vec<FileRecord>,
+    /// Conflicts found and how they were resolved
+    pub conflicts: Vec<ConflictRecord>,
+    /// Actions left undone because the sync was cancelled
+    #[serde(default)]
+    pub undone: Vec<FileRecord>
+}
+
+/// An action of a sync on a single file
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FileRecord {
+    pub path: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ConflictRecord {
+    pub path: String,
+    pub conflict: String,
+    pub resolution: String
+}
+
+impl FileRecord {
+    fn from(action: &Action, error: Option<&str>) -> Self {
+        return FileRecord{ path: action.path.clone(), action: action.action.name().to_string(), error: error.map(str::to_string) };
+    }
+}
+
+impl HistoryEntry {
+    /// Builds the entry of a sync from its report. `conflicts` are the conflicting actions as
+    /// found, before their resolution
+    pub fn new(started: OffsetDateTime, report: &SyncReport, bytes: u64, conflicts: &[(Action, Resolution)]) -> Self {
+        let mut counts = BTreeMap::new();
+        for action in &report.applied {
+            *counts.entry(action.action.name().to_string()).or_insert(0) += 1;
+        }
+
+        return HistoryEntry{
+            started,
+            finished: OffsetDateTime::now_utc(),
+            counts,
+            bytes,
+            files: report.applied.iter().map(|a| FileRecord::from(a, None)).collect(),
+            errors: report.failed.iter().map(|f| FileRecord::from(&f.action, Some(&f.error))).collect(),
+            conflicts: conflicts.iter().map(|(action, resolution)| ConflictRecord{
+                path: action.path.clone(), conflict: action.action.to_string(), resolution: resolution.name().to_string()
+            }).collect(),
+            undone: report.undone.iter().map(|a| FileRecord::from(a, None)).collect()
+        };
+    }
+
+    /// Appends the entry to the history of the link
+    pub fn append(&self, link_path: &str) -> Result<(), Error> {
+        create_dir_all(link_path)?;
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(Path::new(link_path).join("history.jsonl"))?;
+
+        // a line left incomplete by an interrupted append is ended first, or the entry would be lost with it
+        let len = file.metadata()?.len();
+        if len > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' { writeln!(file)?; }
+        }
+
+        let line = serde_json::to_string(self)?;
+        return writeln!(file, "{line}");
+    }
+
+    /// Every entry of the history of a link, oldest first. Links that were never synced have an
+    /// empty history
+    pub fn load_all(link_path: &str) -> Result<Vec<Self>, Error> {
+        let file = match File::open(Path::new(link_path).join("history.jsonl")) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => { return Ok(Vec::new()); }
+            Err(e) => { return Err(e); }
+        };
+
+        // a line left incomplete by an interrupted append is skipped
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(entry) = serde_json::from_str(&line?) { entries.push(entry); }
+        }
+        return Ok(entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+    use super::*;
+    use crate::action::{ActionType, ErrorType, FailedAction};
+    use crate::test_utils::TempDir;
+
+    fn entry(path: &str) -> HistoryEntry {
+        let report = SyncReport{
+            applied: vec![Action::new(path, ActionType::Local2Remote)],
+            failed: vec![FailedAction{ action: Action::new("failed", ActionType::Remote2Local), error: "timeout".to_string() }],
+            deferred: Vec::new(),
+            undone: vec![Action::new("undone", ActionType::DelRemote)]
+        };
+        let conflicts = [(Action::new("conflict", ActionType::Error(ErrorType::TwoNew)), Resolution::KeepBoth)];
+        return HistoryEntry::new(OffsetDateTime::UNIX_EPOCH, &report, 42, &conflicts);
+    }
+
+    #[test]
+    fn entries_are_read_back_in_order() {
+        let link = TempDir::new();
+        assert!(HistoryEntry::load_all(link.str()).unwrap().is_empty());
+
+        entry("first").append(link.str()).unwrap();
+        entry("second").append(link.str()).unwrap();
+
+        let history = HistoryEntry::load_all(link.str()).unwrap();
+        assert_eq!(history.iter().map(|e| e.files[0].path.as_str()).collect::<Vec<&str>>(), vec!["first", "second"]);
+        let first = &history[0];
+        assert_eq!(first.started, OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(first.bytes, 42);
+        assert_eq!(first.counts["upload"], 1);
+        assert_eq!(first.errors[0].error.as_deref(), Some("timeout"));
+        assert_eq!(first.conflicts[0].path, "conflict");
+        assert_eq!(first.undone[0].path, "undone");
+    }
+
+    #[test]
+    fn incomplete_lines_are_skipped() {
+        let link = TempDir::new();
+        let history = link.path().join("history.jsonl");
+        entry("first").append(link.str()).unwrap();
+        // an append interrupted halfway, then a line corrupted on disk
+        let line = serde_json::to_string(&entry("cut")).unwrap();
+        write(&history, format!("{}{}", read_to_string(&history).unwrap(), &line[..line.len() / 2])).unwrap();
+        assert_eq!(HistoryEntry::load_all(link.str()).unwrap().len(), 1);
+
+        entry("second").append(link.str()).unwrap();
+        write(&history, format!("{}not json\n", read_to_string(&history).unwrap())).unwrap();
+
+        let history = HistoryEntry::load_all(link.str()).unwrap();
+        assert_eq!(history.iter().map(|e| e.files[0].path.as_str()).collect::<Vec<&str>>(), vec!["first", "second"]);
+    }
+}
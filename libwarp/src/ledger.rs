@@ -1,41 +1,96 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::{File, create_dir_all};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, create_dir_all, rename};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use std::path::Path;
-use crate::action::{Action, ActionType};
+use crate::action::{Action, ActionType, ErrorType, SyncReport};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct Ledger {
-    pub path_map: HashMap<String, LedgerInfo>
+    pub path_map: HashMap<String, LedgerInfo>,
+    /// Conflicts left unresolved by past syncs. Their files are not transferred until the
+    /// conflict is resolved
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub conflicts: BTreeMap<String, ConflictInfo>
 }
 
-#[derive(Deserialize, Serialize)]
+/// A conflict deferred to a later sync
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ConflictInfo {
+    pub conflict: ErrorType,
+    /// When the conflict was first deferred
+    #[serde(with = "time::serde::rfc3339")]
+    pub since: OffsetDateTime
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct LedgerInfo {
     #[serde(with = "time::serde::rfc3339")]
-    pub last_update: OffsetDateTime
+    pub last_update: OffsetDateTime,
+    /// Hashes checked on both sides after the last transfer of the file, if it was verified
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub hashes: HashMap<String, String>
+}
+
+impl LedgerInfo {
+    pub fn new(last_update: OffsetDateTime) -> Self {
+        return LedgerInfo{ last_update, hashes: HashMap::new() };
+    }
 }
 
 impl Ledger {
     pub fn new() -> Self {
         return Ledger{
-            path_map: Default::default()
+            path_map: Default::default(),
+            conflicts: Default::default()
         };
     }
 
-    pub fn ledger_from(actions: &Vec<Action>) -> Self {
+    /// Builds the ledger resulting from applying `actions`. Files with unresolved conflicts or
+    /// whose action failed or was cancelled were not transferred and keep their entry from the `previous` ledger so
+    /// they are handled again by the next sync. Unresolved conflicts are recorded as such. The verified
+    /// hashes of the other files are taken from `previous`
+    pub fn ledger_from(actions: &[Action], previous: &Ledger, report: &SyncReport) -> Self {
         let update_time = OffsetDateTime::now_utc();
+        let failed = Iterator::chain(report.failed.iter().map(|f| &f.action.path), report.undone.iter().map(|a| &a.path))
+            .collect::<HashSet<&String>>();
         let path_map: HashMap<String, LedgerInfo> = HashMap::from_iter(actions.iter().filter_map(|action|{
+            let previous_info = previous.path_map.get(&action.path);
+            if failed.contains(&action.path) {
+                return previous_info.map(|info| (action.path.clone(), info.clone()));
+            }
+            let updated = || LedgerInfo{ last_update: update_time, hashes: previous_info.map(|info| info.hashes.clone()).unwrap_or_default() };
             return match action.action {
                 ActionType::DelLocal => { None }
                 ActionType::DelRemote => { None }
-                ActionType::Local2Remote => { Some((action.path.clone(), LedgerInfo{last_update: update_time})) }
-                ActionType::Remote2Local => { Some((action.path.clone(), LedgerInfo{last_update: update_time})) }
-                _ => { Some((action.path.clone(), LedgerInfo{last_update: update_time})) }
+                ActionType::Local2Remote => { Some((action.path.clone(), updated())) }
+                ActionType::Remote2Local => { Some((action.path.clone(), updated())) }
+                ActionType::Error(_) => { previous_info.map(|info| (action.path.clone(), info.clone())) }
+                _ => { Some((action.path.clone(), updated())) }
             }
         }));
 
-        return Self{path_map}
+        let conflicts = actions.iter().filter_map(|action| match &action.action {
+            ActionType::Error(conflict) => {
+                let since = previous.conflicts.get(&action.path).map_or(update_time, |info| info.since);
+                Some((action.path.clone(), ConflictInfo{ conflict: conflict.clone(), since }))
+            }
+            _ => None
+        }).collect();
+
+        return Self{path_map, conflicts}
+    }
+
+    /// Replaces the entries of the paths of `actions` with the ones of `other`, which only covers
+    /// these paths
+    pub fn merge(mut self, other: Ledger, actions: &[Action]) -> Self {
+        for action in actions {
+            self.path_map.remove(&action.path);
+            self.conflicts.remove(&action.path);
+        }
+        self.path_map.extend(other.path_map);
+        self.conflicts.extend(other.conflicts);
+        return self;
     }
 
     pub fn load(link_path: &str) -> Self {
@@ -48,14 +103,18 @@ impl Ledger {
         return ledger
     }
 
+    /// The ledger is written to a temporary file first, so an interrupted save never leaves a
+    /// truncated ledger behind
     pub fn save(&self, link_path: &str) {
         create_dir_all(link_path).expect("Unable to create configs/link folder");
         let path = Path::new(link_path).join("ledger.json");
+        let temp = Path::new(link_path).join("ledger.json.tmp");
 
         serde_json::to_writer(
-            File::create(path).unwrap(),
+            File::create(&temp).unwrap(),
             &self
         ).expect("Error while saving ledger");
+        rename(temp, path).expect("Error while saving ledger");
     }
 
     pub fn update_ledger(&mut self, file: &str, action: ActionType) {
@@ -64,9 +123,78 @@ impl Ledger {
                 self.path_map.remove(file);
             }
             ActionType::Local2Remote | ActionType::Remote2Local => {
-                self.path_map.insert(file.to_owned(), LedgerInfo{last_update: OffsetDateTime::now_utc()});
+                self.path_map.insert(file.to_owned(), LedgerInfo::new(OffsetDateTime::now_utc()));
             }
             _ => panic!("unexpected actionType")
         }
     }
-}
\ No newline at end of file
+
+    /// Records the hashes verified after the transfer of a file
+    pub fn set_hashes(&mut self, file: &str, hashes: HashMap<String, String>) {
+        if let Some(info) = self.path_map.get_mut(file) { info.hashes = hashes; }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+    use super::*;
+    use crate::action::FailedAction;
+
+    fn previous() -> Ledger {
+        let mut ledger = Ledger::new();
+        let old = OffsetDateTime::UNIX_EPOCH + Duration::seconds(10);
+        for path in ["copied", "deleted", "failed", "conflict", "untouched"] {
+            ledger.path_map.insert(path.to_string(), LedgerInfo::new(old));
+        }
+        ledger.set_hashes("copied", HashMap::from([("md5".to_string(), "abc".to_string())]));
+        ledger.conflicts.insert("conflict".to_string(), ConflictInfo{ conflict: ErrorType::TwoSideMod, since: old });
+        return ledger;
+    }
+
+    fn actions() -> Vec<Action> {
+        return vec![
+            Action::new("copied", ActionType::Local2Remote),
+            Action::new("deleted", ActionType::DelRemote),
+            Action::new("failed", ActionType::Remote2Local),
+            Action::new("conflict", ActionType::Error(ErrorType::TwoSideMod)),
+            Action::new("new", ActionType::Remote2Local)
+        ];
+    }
+
+    #[test]
+    fn ledger_from_keeps_failed_and_conflicting_entries() {
+        let previous = previous();
+        let actions = actions();
+        let report = SyncReport{
+            failed: vec![FailedAction{ action: actions[2].clone(), error: "error".to_string() }],
+            ..SyncReport::default()
+        };
+
+        let ledger = Ledger::ledger_from(&actions, &previous, &report);
+
+        let old = previous.path_map["failed"].last_update;
+        assert!(ledger.path_map["copied"].last_update > old);
+        assert_eq!(ledger.path_map["copied"].hashes, previous.path_map["copied"].hashes);
+        assert!(ledger.path_map.contains_key("new"));
+        assert!(!ledger.path_map.contains_key("deleted"));
+        assert_eq!(ledger.path_map["failed"].last_update, old);
+        assert_eq!(ledger.path_map["conflict"].last_update, old);
+        // the conflict keeps the time it was first deferred
+        assert_eq!(ledger.conflicts["conflict"].since, old);
+        // files without action are not part of a full sync anymore
+        assert!(!ledger.path_map.contains_key("untouched"));
+    }
+
+    #[test]
+    fn merge_only_replaces_the_paths_of_the_actions() {
+        let previous = previous();
+        let actions = vec![Action::new("conflict", ActionType::Local2Remote)];
+        let other = Ledger::ledger_from(&actions, &previous, &SyncReport::default());
+
+        let ledger = previous.merge(other, &actions);
+
+        assert!(ledger.conflicts.is_empty());
+        assert!(ledger.path_map["conflict"].last_update > ledger.path_map["untouched"].last_update);
+        assert_eq!(ledger.path_map.len(), 5);
+    }
+}
@@ -1,4 +1,19 @@
 pub mod action;
+mod apply;
+pub mod backend;
 pub mod configs;
+pub mod conflict;
+pub mod engine;
+pub mod event;
+pub mod history;
 pub mod ledger;
-pub mod rclone;
\ No newline at end of file
+pub mod lock;
+#[cfg(any(feature = "rclone", feature = "rcd"))]
+pub mod rclone;
+#[cfg(feature = "rcd")]
+pub mod rcd;
+pub mod scan;
+pub mod session;
+#[cfg(test)]
+mod test_utils;
+pub mod watch;
\ No newline at end of file
@@ -0,0 +1,104 @@
+use std::fs::{File, OpenOptions, TryLockError, create_dir_all, read_to_string};
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+use std::{process, thread};
+use std::time::Duration;
+
+/// Exclusive lock on a link, held for the whole duration of a sync. The lock is an advisory lock
+/// of the OS on the `sync.lock` file of the link folder, so it is released when dropped or when its
+/// owner dies. The file holds the PID of the owner, only to tell who is holding the lock
+pub struct SyncLock {
+    file: File
+}
+
+impl SyncLock {
+    /// Tries to take the lock of the link. Fails with `ErrorKind::WouldBlock` if another process,
+    /// or another `SyncLock` of this process, holds it. A file left behind by a dead owner is not
+    /// locked anymore and is taken over
+    pub fn acquire(link_path: &str) -> Result<Self, Error> {
+        create_dir_all(link_path)?;
+        let path = Path::new(link_path).join("sync.lock");
+
+        // the file is never truncated before being locked, the owner's PID is kept for the message below
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let owner = read_to_string(&path).unwrap_or_default();
+                return Err(Error::new(ErrorKind::WouldBlock, format!("link is busy (locked by process {})", owner.trim())));
+            }
+            Err(TryLockError::Error(e)) => { return Err(e); }
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", process::id())?;
+        return Ok(SyncLock{ file });
+    }
+
+    /// Same as `acquire` but waits for the lock to be released instead of failing
+    pub fn wait(link_path: &str) -> Result<Self, Error> {
+        loop {
+            match Self::acquire(link_path) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => { thread::sleep(Duration::from_millis(500)); }
+                res => { return res; }
+            }
+        }
+    }
+}
+
+impl Drop for SyncLock {
+    /// The file is kept, removing it could let two processes lock two different files
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::time::Instant;
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn second_lock_is_refused() {
+        let link = TempDir::new();
+        let _lock = SyncLock::acquire(link.str()).unwrap();
+
+        let err = SyncLock::acquire(link.str()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert!(err.to_string().contains(&process::id().to_string()));
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let link = TempDir::new();
+        drop(SyncLock::acquire(link.str()).unwrap());
+
+        assert!(SyncLock::acquire(link.str()).is_ok());
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let link = TempDir::new();
+        // left behind by a process that died while holding the lock
+        write(link.path().join("sync.lock"), "4194303").unwrap();
+
+        let _lock = SyncLock::acquire(link.str()).unwrap();
+        assert_eq!(read_to_string(link.path().join("sync.lock")).unwrap(), process::id().to_string());
+    }
+
+    #[test]
+    fn wait_returns_once_released() {
+        let link = TempDir::new();
+        let lock = SyncLock::acquire(link.str()).unwrap();
+        let started = Instant::now();
+
+        thread::scope(|s| {
+            s.spawn(|| { thread::sleep(Duration::from_millis(200)); drop(lock); });
+            assert!(SyncLock::wait(link.str()).is_ok());
+        });
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
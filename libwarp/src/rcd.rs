@@ -0,0 +1,58 @@
+use std::io::Read;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crate::configs::RcdConfig;
+
+/// Maximum time to reach the server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum wait for an answer. Transfers run as jobs polled with short calls, only the listing
+/// of a large remote takes long
+const READ_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Client of the remote control API of an external `rclone rcd` server
+#[derive(Clone)]
+pub struct RcdClient {
+    url: String,
+    /// Value of the `Authorization` header, if the server requires authentication
+    auth: Option<String>,
+    agent: ureq::Agent
+}
+
+impl RcdClient {
+    pub fn new(config: &RcdConfig) -> Self {
+        let auth = config.user.as_ref().map(|user| {
+            let credentials = format!("{}:{}", user, config.pass.as_deref().unwrap_or(""));
+            format!("Basic {}", STANDARD.encode(credentials))
+        });
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(READ_TIMEOUT)
+            .timeout_write(READ_TIMEOUT)
+            .build();
+        return RcdClient{ url: config.url.trim_end_matches('/').to_string(), auth, agent }
+    }
+
+    /// Calls a method of the API. Like `librclone::rpc`, the result and the errors are returned as
+    /// the JSON sent by rclone, or as the message of the HTTP error if the server could not answer
+    pub fn rpc(&self, method: &str, input: String) -> Result<String, String> {
+        let mut request = self.agent.post(&format!("{}/{}", self.url, method))
+            .set("Content-Type", "application/json");
+        if let Some(auth) = &self.auth { request = request.set("Authorization", auth); }
+
+        return match request.send_string(&input) {
+            Ok(response) => read_body(response),
+            Err(ureq::Error::Status(_, response)) => Err(read_body(response)?),
+            Err(e) => Err(e.to_string())
+        };
+    }
+}
+
+/// Reads the whole body of a response. Listings of large remotes are bigger than what
+/// `Response::into_string` accepts
+fn read_body(response: ureq::Response) -> Result<String, String> {
+    let mut body = String::new();
+    response.into_reader().read_to_string(&mut body).map_err(|e| e.to_string())?;
+    return Ok(body);
+}
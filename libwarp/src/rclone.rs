@@ -1,10 +1,18 @@
 use std::collections::HashMap;
+use std::{env, fs, io, process, thread};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use serde::Deserialize;
 use serde_json::json;
-use rayon::prelude::*;
 use time::OffsetDateTime;
-use std::sync::mpsc::{Sender};
-use crate::action::{Action, ActionType};
+use crate::backend::{Backend, CopyError, RFileInfo, TransferContext};
+use crate::engine::CancelToken;
+use crate::event::{SyncEvent, TransferStats};
+#[cfg(feature = "rcd")]
+use crate::rcd::RcdClient;
 
 
 #[derive(Deserialize)]
@@ -12,144 +20,319 @@ pub struct RListResult {
     pub list: Vec<RFileInfo>
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize, Clone)]
-pub struct RFileInfo {
-    #[serde(rename = "Path")]
-    pub path: String,
-    //#[serde(rename = "Name")]
-    //pub name: String,
-    #[serde(rename = "Size")]
-    pub size: i64,
-    // pub MimeType: String,
-    #[serde(rename = "ModTime", with = "time::serde::rfc3339")]
-    pub mod_time: OffsetDateTime,
-    #[serde(rename = "IsDir")]
-    pub is_dir: bool,
-    // pub ID: String,
+/// Subset of the result of `core/stats`
+#[derive(Deserialize)]
+struct RStats {
+    bytes: u64,
+    #[serde(rename = "totalBytes")]
+    total_bytes: u64,
+    speed: f64,
+    eta: Option<u64>,
+    #[serde(default)]
+    transferring: Vec<RTransfer>
 }
 
+#[derive(Deserialize)]
+struct RTransfer {
+    name: String,
+    bytes: u64,
+    size: i64
+}
+
+/// Result of `operations/stat`
+#[derive(Deserialize)]
+struct RStatResult {
+    item: Option<RFileInfo>
+}
+
+/// Result of `core/obscure`
+#[derive(Deserialize)]
+struct RObscured {
+    obscured: String
+}
 
+/// Result of a call made with `_async`
+#[derive(Deserialize)]
+struct RJob {
+    jobid: u64
+}
+
+/// Subset of the result of `job/status`
+#[derive(Deserialize)]
+struct RJobStatus {
+    finished: bool,
+    success: bool,
+    #[serde(default)]
+    error: String
+}
+
+static TEMP_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// How rclone is reached
 #[derive(Clone)]
-pub struct RClone {
-    local: String,
-    remote: String
+enum Transport {
+    /// The librclone library built in warp
+    #[cfg(feature = "rclone")]
+    Embedded,
+    #[cfg(feature = "rcd")]
+    Server(RcdClient)
 }
 
+/// Remote accessed through rclone, either the librclone library built in warp or an external
+/// `rclone rcd` server. The embedded rclone must be initialized by a live `SyncEngine` before any
+/// of the methods are used. Since files are moved in and out of rclone through temporary files, a
+/// server must run on the same machine
+#[derive(Clone)]
+pub struct RCloneBackend {
+    fs: String,
+    transport: Transport
+}
 
-impl RClone {
-    pub fn new(local: &str, remote: &str) -> Self {
-        librclone::initialize();
-        return RClone{
-            local: local.to_string(),
-            remote: remote.to_string()
-        }
+impl RCloneBackend {
+    /// `fs` is any path understood by rclone, like `remote:folder`
+    #[cfg(feature = "rclone")]
+    pub fn new(fs: &str) -> Self {
+        return RCloneBackend{ fs: fs.to_string(), transport: Transport::Embedded }
+    }
+
+    /// Same as `new` but with the rclone of an `rclone rcd` server, and the remotes of its config
+    #[cfg(feature = "rcd")]
+    pub fn with_server(fs: &str, server: RcdClient) -> Self {
+        return RCloneBackend{ fs: fs.to_string(), transport: Transport::Server(server) }
+    }
+
+    /// Wraps the remote in an rclone crypt layer using `password` and `salt`. Files are encrypted
+    /// on the remote and handled as plain files by warp
+    pub fn encrypted(self, password: &str, salt: &str) -> Result<Self, Error> {
+        let password = self.obscure(password)?;
+        let salt = self.obscure(salt)?;
+        // values of connection strings are quoted, with the quotes they contain doubled
+        let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+
+        let fs = format!(":crypt,remote={},password={},password2={}:", quote(&self.fs), quote(&password), quote(&salt));
+        return Ok(RCloneBackend{ fs, transport: self.transport });
+    }
+
+    /// Obscures a secret the way rclone expects it in configs
+    fn obscure(&self, clear: &str) -> Result<String, Error> {
+        let res = self.rpc("core/obscure", json!({ "clear": clear }))?;
+        let res: RObscured = serde_json::from_str(&res).map_err(Error::other)?;
+        return Ok(res.obscured);
     }
 
+    /// Calls rclone, turning its errors into `io::Error`
+    fn rpc(&self, method: &str, input: serde_json::Value) -> Result<String, Error> {
+        let res = match &self.transport {
+            #[cfg(feature = "rclone")]
+            Transport::Embedded => librclone::rpc(method, input.to_string()),
+            #[cfg(feature = "rcd")]
+            Transport::Server(server) => server.rpc(method, input.to_string())
+        };
+        return res.map_err(|e| rpc_error(&e));
+    }
 
+    /// Runs a method as an async rclone job and waits for it to finish. The job is stopped if the
+    /// sync is cancelled or if it runs longer than the timeout of `ctx`
+    fn run_job(&self, method: &str, mut input: serde_json::Value, ctx: &TransferContext) -> Result<(), Error> {
+        input["_async"] = json!(true);
+        if !ctx.group.is_empty() { input["_group"] = json!(ctx.group); }
+        let job: RJob = serde_json::from_str(&self.rpc(method, input)?).map_err(Error::other)?;
 
-    fn sort_actions(actions: &Vec<Action>) -> HashMap<ActionType, Vec<String>> {
-        let mut map: HashMap<ActionType, Vec<String>> = HashMap::new();
-        for action in actions {
-            match map.get_mut(&action.action) {
-                None => { map.insert(action.action.clone(), vec![action.path.clone()]); }
-                Some(list) => { list.push(action.path.clone()); }
+        let start = Instant::now();
+        // short jobs are common, the polling slows down for the long ones
+        let mut poll = Duration::from_millis(10);
+        loop {
+            let stop = ctx.check().err().or_else(|| {
+                ctx.timeout.filter(|t| &start.elapsed() > t)
+                    .map(|t| Error::new(ErrorKind::TimedOut, format!("{method} did not finish within {}s", t.as_secs())))
+            });
+            if let Some(e) = stop {
+                let _ = self.rpc("job/stop", json!({ "jobid": job.jobid }));
+                return Err(e);
             }
-        }
-        return map;
-    }
-
-    fn batch_actions(actions: HashMap<ActionType, Vec<String>>, size: usize) -> HashMap<(ActionType, usize), Vec<String>> {
-        let mut new_map = HashMap::new();
-        actions.iter().for_each(|(k, v)|{
-            match size {
-                0 => { new_map.insert((k.clone(), 0), v.clone());  }
-                _ => {
-                    v.chunks(size).enumerate().for_each(|(i, v)|{
-                        new_map.insert((k.clone(), i), Vec::from(v));
-                    });
-                }
+
+            let status: RJobStatus = serde_json::from_str(&self.rpc("job/status", json!({ "jobid": job.jobid }))?).map_err(Error::other)?;
+            if status.finished {
+                return match status.success {
+                    true => Ok(()),
+                    false => Err(job_error(&status.error))
+                };
             }
+
+            thread::sleep(poll);
+            poll = (poll * 2).min(Duration::from_millis(500));
+        }
+    }
+
+    /// Copies a single file. Unlike filter rules, which are globs, the paths are taken literally.
+    /// The copy always runs as a job, so that no call to a server waits for a whole transfer.
+    /// Without `ctx`, it cannot be cancelled and has no time limit
+    fn copy_file(&self, src_fs: &str, src: &str, dst_fs: &str, dst: &str, ctx: Option<&TransferContext>) -> Result<(), Error> {
+        let input = json!({
+            "srcFs": src_fs, "srcRemote": src,
+            "dstFs": dst_fs, "dstRemote": dst
         });
+        let unbounded;
+        let ctx = match ctx {
+            Some(ctx) => ctx,
+            None => {
+                unbounded = TransferContext{ group: String::new(), timeout: None, cancel: CancelToken::new() };
+                &unbounded
+            }
+        };
+        return self.run_job("operations/copyfile", input, ctx);
+    }
 
-        return new_map;
+    fn stat_item(&self, path: &str, hashes: bool) -> Result<Option<RFileInfo>, Error> {
+        let res = self.rpc("operations/stat", json!({
+            "fs": self.fs, "remote": path,
+            "opt": { "showHash": hashes }
+        }))?;
+        let res: RStatResult = serde_json::from_str(&res).map_err(Error::other)?;
+        return Ok(res.item);
     }
 
-    pub fn apply_actions(&self, actions: &Vec<Action>, pipe: Option<Sender<(bool, String, ActionType)>>, thread_nb: usize, batch_size: usize) {
-        rayon::ThreadPoolBuilder::new().num_threads(thread_nb).build_global().unwrap();
+    /// Path of a temporary file used to move content in and out of rclone
+    fn temp_file() -> PathBuf {
+        return env::temp_dir().join(format!("warp-{}-{}", process::id(), TEMP_FILE.fetch_add(1, Ordering::Relaxed)));
+    }
+}
 
-        let actions = Self::sort_actions(actions);
-        let actions = Self::batch_actions(actions, batch_size);
-        let lazy_result = actions.par_iter().map(
-            |((a, _), list)| self.execute(a, list, &pipe)
-        );
+/// Downloaded copy of a remote file, read from disk and removed once dropped
+struct TempFile {
+    file: File,
+    path: PathBuf
+}
 
-        lazy_result.reduce(|| Ok("".to_string()), |acc, r| {
-            if r.is_ok() && acc.is_ok() { return acc; }
-            if r.is_ok() && acc.is_err() { return acc; }
-            if r.is_err() && acc.is_ok() { return Err(r.err().unwrap()); }
-            else { Err(format!("{}\n{}", r.err().unwrap(), acc.err().unwrap()) ) }
-        }).unwrap();
+impl Read for TempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.file.read(buf);
     }
+}
 
-    pub fn local_list(&self) -> Vec<RFileInfo> { Self::get_file_list(&self.local) }
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
 
-    pub fn remote_list(&self) -> Vec<RFileInfo> { Self::get_file_list(&self.remote) }
+impl Backend for RCloneBackend {
+    fn fs(&self) -> Option<String> {
+        return Some(self.fs.clone());
+    }
 
-    fn execute(&self, a: &ActionType, list: &Vec<String>, pipe: &Option<Sender<(bool, String, ActionType)>>) -> Result<String, String> {
-        if a == &ActionType::Nothing { return Ok("Noting to do".to_string()); }
+    fn list(&self, hashes: bool) -> Result<Vec<RFileInfo>, Error> {
+        let res = self.rpc("operations/list", json!({
+            "fs": self.fs, "remote": "",
+            "opt": { "recurse": true, "showHash": hashes },
+            "_config": {"fastList": true}
+        }))?;
 
-        // sending to pipe starting signal for files
-        if let Some(tx) = &pipe {
-            list.iter().for_each( |s| tx.send((true, s.clone(), a.clone())).expect("Error while sending update"))
-        }
+        let res: RListResult = serde_json::from_str(&res).map_err(Error::other)?;
+        return Ok(res.list);
+    }
 
-        // doing necessary action
-        let res = match a {
-            ActionType::DelLocal =>     { RClone::delete_files(&self.local, list) }
-            ActionType::DelRemote =>    { RClone::delete_files(&self.remote, list) }
-            ActionType::Local2Remote => { RClone::copy_files(&self.local, &self.remote, list) }
-            ActionType::Remote2Local => { RClone::copy_files(&self.remote, &self.local, list) }
-            _ => { Err(format!("An unexpected ActionType found during resolution ({a}). ").to_string()) }
+    fn stat(&self, path: &str) -> Result<Option<RFileInfo>, Error> {
+        return self.stat_item(path, false);
+    }
+
+    fn hash(&self, path: &str) -> Result<HashMap<String, String>, Error> {
+        return match self.stat_item(path, true)? {
+            None => Err(Error::new(ErrorKind::NotFound, format!("file not found: {path}"))),
+            Some(file) => Ok(file.hashes)
         };
+    }
 
-        // sending to pipe ending signal for files
-        if let Some(tx) = &pipe {
-            list.iter().for_each( |s| tx.send((false, s.clone(), a.clone())).expect("Error while sending update"))
-        }
+    /// The file is downloaded to a temporary file first
+    fn read(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> {
+        let temp = Self::temp_file();
+        let res = self.copy_file(&self.fs, path, temp.parent().unwrap().to_str().unwrap(), temp.file_name().unwrap().to_str().unwrap(), None)
+            .and_then(|_| File::open(&temp));
+        return match res {
+            Ok(file) => Ok(Box::new(TempFile{ file, path: temp })),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        };
+    }
 
+    /// The content is written to a temporary file first, which is then uploaded
+    fn write(&self, path: &str, content: &mut dyn Read, mod_time: OffsetDateTime) -> Result<(), Error> {
+        let temp = Self::temp_file();
+        let res = File::create(&temp)
+            .and_then(|mut file| { io::copy(content, &mut file)?; file.set_modified(mod_time.into()) })
+            .and_then(|_| self.copy_file(temp.parent().unwrap().to_str().unwrap(), temp.file_name().unwrap().to_str().unwrap(), &self.fs, path, None));
+        let _ = fs::remove_file(&temp);
         return res;
     }
 
-    fn get_file_list(fs: &str) -> Vec<RFileInfo> {
-        let res = librclone::rpc("operations/list",
-            json!({
-                "fs": fs, "remote": "",
-                "opt": { "recurse": true },
-                "_config": {"fastList": true}
-            }).to_string()
-        );
+    fn delete(&self, files: &[String], ctx: &TransferContext) -> Result<(), Error> {
+        for path in files {
+            let res = self.run_job("operations/deletefile", json!({ "fs": self.fs, "remote": path }), ctx);
+            match res {
+                Err(e) if e.kind() != ErrorKind::NotFound => { return Err(e); }
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+
+    fn move_file(&self, from: &str, to: &str) -> Result<(), Error> {
+        return self.rpc("operations/movefile", json!({
+            "srcFs": self.fs, "srcRemote": from,
+            "dstFs": self.fs, "dstRemote": to
+        })).map(|_| ());
+    }
 
-        let res: RListResult = serde_json::from_str(&res.unwrap()).unwrap();
-        return res.list;
+    fn rclone_copy(&self, from: &str, to: &str, files: &[String], ctx: &TransferContext) -> Option<Result<(), CopyError>> {
+        let mut copied = Vec::new();
+        for path in files {
+            if let Err(error) = self.copy_file(from, path, to, path, Some(ctx)) {
+                return Some(Err(CopyError{ copied, error }));
+            }
+            copied.push(path.clone());
+        }
+        return Some(Ok(()));
     }
 
-    fn copy_files(from: &str, to: &str, files: &Vec<String>) -> Result<String, String> {
-        librclone::rpc("sync/copy",
-            json!({
-                "srcFs": from, "dstFs": to,
-                "_filter": { "IncludeRule": files },
-                "_config": {"NoCheckDest": true}
-            }).to_string()
-        )
+    fn stats(&self, group: &str) -> Option<Vec<SyncEvent>> {
+        let stats = self.rpc("core/stats", json!({ "group": group })).ok()
+            .and_then(|res| serde_json::from_str::<RStats>(&res).ok())?;
+
+        let mut events = stats.transferring.into_iter()
+            .map(|t| SyncEvent::ActionProgress{ path: t.name, bytes: t.bytes, size: t.size.max(0) as u64 })
+            .collect::<Vec<SyncEvent>>();
+        events.push(SyncEvent::Stats(TransferStats{
+            bytes: stats.bytes, total_bytes: stats.total_bytes, speed: stats.speed, eta: stats.eta
+        }));
+        return Some(events);
     }
 
-    fn delete_files(from: &str, files: &Vec<String>) -> Result<String, String> {
-        librclone::rpc("operations/delete",
-            json!({
-                "fs": from,
-                "_filter": { "IncludeRule": files }
-            }).to_string()
-        )
+    fn clear_stats(&self, group: &str) {
+        let _ = self.rpc("core/stats-delete", json!({ "group": group }));
     }
 }
+
+/// Converts the error of a failed job. Jobs only report a message, so missing files are
+/// recognized by the messages of rclone
+fn job_error(message: &str) -> Error {
+    return match message {
+        "object not found" | "directory not found" => Error::new(ErrorKind::NotFound, message),
+        _ => Error::other(message)
+    };
+}
+
+/// Converts an rclone error, which is returned as a JSON object holding its message and HTTP
+/// status. Missing files are reported with `ErrorKind::NotFound`
+fn rpc_error(error: &str) -> Error {
+    let value = serde_json::from_str::<serde_json::Value>(error).ok();
+    let message = value.as_ref()
+        .and_then(|v| v.get("error").and_then(|e| e.as_str()).map(str::to_string))
+        .unwrap_or(error.trim().to_string());
+
+    return match value.and_then(|v| v.get("status").and_then(|s| s.as_u64())) {
+        Some(404) => Error::new(ErrorKind::NotFound, message),
+        _ => Error::other(message)
+    };
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, Metadata, create_dir_all, read_dir, symlink_metadata};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use crate::backend::{LocalBackend, RFileInfo, file_info};
+
+/// Content of a directory as seen during the last scan
+#[derive(Deserialize, Serialize)]
+struct CachedDir {
+    #[serde(with = "time::serde::rfc3339")]
+    mod_time: OffsetDateTime,
+    files: Vec<RFileInfo>,
+    dirs: Vec<String>
+}
+
+/// Native scanner for the local side of a link. The result of every scan is cached in the link
+/// folder so the next scans only need to look at what changed. This cache is kept apart from the
+/// ledger, which only records the files synced and not the directories as they were on disk
+pub struct LocalScanner {
+    root: PathBuf,
+    backend: LocalBackend,
+    dirs: HashMap<String, CachedDir>
+}
+
+impl LocalScanner {
+    /// Creates a scanner for `local` using the cache saved in `link_path` if there is one
+    pub fn load(local: &str, link_path: &str) -> Self {
+        let path = Path::new(link_path).join("scan_cache.json");
+        let dirs = File::open(path).ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default();
+
+        return LocalScanner{ root: PathBuf::from(local), backend: LocalBackend::new(local), dirs }
+    }
+
+    pub fn save(&self, link_path: &str) {
+        create_dir_all(link_path).expect("Unable to create configs/link folder");
+        let path = Path::new(link_path).join("scan_cache.json");
+
+        serde_json::to_writer(
+            File::create(path).unwrap(),
+            &self.dirs
+        ).expect("Error while saving scan cache");
+    }
+
+    /// Scans the whole local folder. Directories left unchanged since the last scan are not read
+    /// again, only their files are checked for modifications. Directories never scanned are walked
+    /// in parallel on the current rayon pool
+    pub fn scan(&mut self) -> Result<Vec<RFileInfo>, Error> {
+        self.scan_dir("", true)?;
+        return Ok(self.list());
+    }
+
+    /// Scans only the given paths, relative to the local folder, and trusts the cache for
+    /// everything else. The paths are usually given by a `LocalWatcher`. Directories are scanned
+    /// with their whole content
+    pub fn scan_paths(&mut self, paths: &HashSet<String>) -> Result<Vec<RFileInfo>, Error> {
+        if !self.dirs.contains_key("") { return self.scan(); }
+
+        let mut parents: HashSet<String> = HashSet::new();
+        let mut subtrees: HashSet<String> = HashSet::new();
+        for path in paths {
+            // the closest cached parent holds the entry of the path
+            let mut parent = parent_of(path);
+            while !self.dirs.contains_key(parent) { parent = parent_of(parent); }
+            parents.insert(parent.to_string());
+
+            if self.root.join(path).is_dir() { subtrees.insert(path.clone()); }
+        }
+
+        for parent in parents { self.scan_dir(&parent, false)?; }
+        for subtree in subtrees { self.read_tree(&subtree)?; }
+
+        return Ok(self.list());
+    }
+
+    /// Files and directories as seen by the last scan
+    pub fn list(&self) -> Vec<RFileInfo> {
+        let mut list = Vec::new();
+        for (path, dir) in &self.dirs {
+            if !path.is_empty() {
+                list.push(RFileInfo{
+                    path: path.clone(), size: -1, mod_time: dir.mod_time, is_dir: true,
+                    hashes: HashMap::new(), inode: None, device: None
+                });
+            }
+            list.extend(dir.files.iter().cloned());
+        }
+        return list;
+    }
+
+    /// Updates the cached content of a directory. Unless `deep` is set, only the subdirectories
+    /// that were never scanned are scanned
+    fn scan_dir(&mut self, path: &str, deep: bool) -> Result<(), Error> {
+        if !self.dirs.contains_key(path) { return self.read_tree(path); }
+
+        let meta = match symlink_metadata(self.root.join(path)) {
+            Ok(meta) if meta.is_dir() => meta,
+            // the directory was removed since it was listed
+            _ => { self.forget(path); return Ok(()); }
+        };
+
+        let unchanged = self.dirs.get(path).is_some_and(|d| Some(d.mod_time) == mod_time(&meta));
+        if !unchanged { return self.read_dir(path, deep); }
+
+        // the entries of an unchanged directory are the same but the files may have been modified
+        let cached = self.dirs.get_mut(path).unwrap();
+        let root = &self.root;
+        cached.files.retain_mut(|file| {
+            match symlink_metadata(root.join(&file.path)).ok().filter(|m| m.is_file()) {
+                None => false,
+                Some(meta) => {
+                    *file = file_info(&file.path, &meta, HashMap::new());
+                    true
+                }
+            }
+        });
+
+        if deep {
+            for dir in cached.dirs.clone() { self.scan_dir(&join(path, &dir), deep)?; }
+        }
+        return Ok(());
+    }
+
+    /// Reads the entries of a directory from scratch. Subdirectories are read if `deep` is set or
+    /// if they were never scanned
+    fn read_dir(&mut self, path: &str, deep: bool) -> Result<(), Error> {
+        let dir_path = self.root.join(path);
+        let meta = match symlink_metadata(&dir_path) {
+            Ok(meta) if meta.is_dir() => meta,
+            _ => { self.forget(path); return Ok(()); }
+        };
+
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for entry in read_dir(&dir_path)? {
+            let entry = entry?;
+            // like rclone, symlinks and non UTF-8 names are skipped
+            let Ok(meta) = entry.metadata() else { continue; };
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue; };
+
+            if meta.is_dir() { dirs.push(name); }
+            else if meta.is_file() { files.push(file_info(&join(path, &name), &meta, HashMap::new())); }
+        }
+
+        // forgets the subdirectories that were removed
+        if let Some(old) = self.dirs.get(path) {
+            let removed = old.dirs.iter().filter(|d| !dirs.contains(d)).map(|d| join(path, d)).collect::<Vec<String>>();
+            removed.iter().for_each(|d| self.forget(d));
+        }
+
+        let mod_time = mod_time(&meta).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        self.dirs.insert(path.to_string(), CachedDir{ mod_time, files, dirs: dirs.clone() });
+
+        for dir in dirs {
+            let sub_path = join(path, &dir);
+            if deep { self.scan_dir(&sub_path, deep)?; }
+            else if !self.dirs.contains_key(&sub_path) { self.read_tree(&sub_path)?; }
+        }
+        return Ok(());
+    }
+
+    /// Reads a directory and all its subdirectories from scratch, with the parallel walk of the
+    /// local backend
+    fn read_tree(&mut self, path: &str) -> Result<(), Error> {
+        let meta = match symlink_metadata(self.root.join(path)) {
+            Ok(meta) if meta.is_dir() => meta,
+            _ => { self.forget(path); return Ok(()); }
+        };
+        let list = self.backend.walk(path, false)?;
+
+        let mod_time = mod_time(&meta).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        let mut tree = HashMap::from([(path.to_string(), CachedDir{ mod_time, files: Vec::new(), dirs: Vec::new() })]);
+        for dir in list.iter().filter(|f| f.is_dir) {
+            tree.insert(dir.path.clone(), CachedDir{ mod_time: dir.mod_time, files: Vec::new(), dirs: Vec::new() });
+        }
+        for entry in list {
+            let (parent, name) = entry.path.rsplit_once('/').unwrap_or(("", &entry.path));
+            let parent = tree.get_mut(parent).expect("walked entry outside of its tree");
+            if entry.is_dir { parent.dirs.push(name.to_string()); } else { parent.files.push(entry); }
+        }
+
+        self.forget(path);
+        self.dirs.extend(tree);
+        return Ok(());
+    }
+
+    fn forget(&mut self, path: &str) {
+        let prefix = format!("{path}/");
+        self.dirs.retain(|p, _| p != path && !p.starts_with(&prefix));
+    }
+}
+
+fn mod_time(meta: &Metadata) -> Option<OffsetDateTime> {
+    return meta.modified().ok().map(OffsetDateTime::from);
+}
+
+fn join(dir: &str, name: &str) -> String {
+    return if dir.is_empty() { name.to_string() } else { format!("{dir}/{name}") };
+}
+
+fn parent_of(path: &str) -> &str {
+    return path.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn size_of(list: &[RFileInfo], path: &str) -> Option<i64> {
+        return list.iter().find(|f| f.path == path).map(|f| f.size);
+    }
+
+    fn sample_tree() -> TempDir {
+        let dir = TempDir::new();
+        create_dir_all(dir.path().join("a/b")).unwrap();
+        write(dir.path().join("top.txt"), "top").unwrap();
+        write(dir.path().join("a/b/deep.txt"), "deep").unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn scan_lists_files_and_dirs() {
+        let dir = sample_tree();
+        let list = LocalScanner::load(dir.str(), dir.str()).scan().unwrap();
+
+        assert_eq!(size_of(&list, "top.txt"), Some(3));
+        assert_eq!(size_of(&list, "a/b/deep.txt"), Some(4));
+        assert!(list.iter().any(|f| f.path == "a/b" && f.is_dir));
+    }
+
+    #[test]
+    fn unchanged_tree_is_not_restatted() {
+        let dir = sample_tree();
+        let link = TempDir::new();
+        let mut scanner = LocalScanner::load(dir.str(), link.str());
+        scanner.scan().unwrap();
+        scanner.save(link.str());
+
+        // modified behind the back of the scanner: the watcher reported nothing
+        write(dir.path().join("a/b/deep.txt"), "modified").unwrap();
+        let mut scanner = LocalScanner::load(dir.str(), link.str());
+        let list = scanner.scan_paths(&HashSet::new()).unwrap();
+        assert_eq!(size_of(&list, "a/b/deep.txt"), Some(4));
+
+        // only the reported path is looked at again
+        let list = scanner.scan_paths(&HashSet::from(["top.txt".to_string()])).unwrap();
+        assert_eq!(size_of(&list, "a/b/deep.txt"), Some(4));
+
+        let list = scanner.scan_paths(&HashSet::from(["a/b/deep.txt".to_string()])).unwrap();
+        assert_eq!(size_of(&list, "a/b/deep.txt"), Some(8));
+    }
+
+    #[test]
+    fn full_scan_sees_changes_in_unchanged_dirs() {
+        let dir = sample_tree();
+        let mut scanner = LocalScanner::load(dir.str(), dir.str());
+        scanner.scan().unwrap();
+
+        write(dir.path().join("a/b/deep.txt"), "modified").unwrap();
+        write(dir.path().join("a/new.txt"), "new").unwrap();
+        let list = scanner.scan().unwrap();
+
+        assert_eq!(size_of(&list, "a/b/deep.txt"), Some(8));
+        assert_eq!(size_of(&list, "a/new.txt"), Some(3));
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use time::OffsetDateTime;
+use crate::action::{Action, ActionType, ErrorType, FailedAction, Preference, SyncReport, gen_action_list, gen_adoption_list};
+use crate::backend::{Backend, LocalBackend, RFileInfo, hash_content};
+use crate::configs::Config;
+use crate::conflict::{ConflictResolver, Resolution, StaticResolver, conflict_name};
+use crate::engine::{CancelToken, RetryPolicy, SyncEngine};
+use crate::event::{Side, SyncEvent};
+use crate::history::HistoryEntry;
+use crate::ledger::{Ledger, LedgerInfo};
+use crate::lock::SyncLock;
+#[cfg(feature = "rcd")]
+use crate::rcd::RcdClient;
+#[cfg(any(feature = "rclone", feature = "rcd"))]
+use crate::rclone::RCloneBackend;
+use crate::scan::LocalScanner;
+
+/// Local and remote backends of a link
+type Backends = (Box<dyn Backend>, Box<dyn Backend>);
+
+/// Conflicts of a sync, as found, with their resolution
+type Resolved = Vec<(Action, Resolution)>;
+
+/// Options of a single sync
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Number of files put in a single rclone request. If 0 the maximum batch size is used
+    pub batch_size: usize,
+    pub retry: RetryPolicy,
+    /// Maximum duration of a single transfer operation. Operations running longer are stopped
+    /// and retried like failing ones
+    pub timeout: Option<Duration>,
+    /// Cancels the sync when triggered
+    pub cancel: CancelToken,
+    /// Passphrase of links encrypted with one
+    pub passphrase: Option<String>,
+    /// Checks the content of every copied file on both sides, even if the link does not ask for it
+    pub verify: bool,
+    /// If the link is already being synced, waits for it to finish instead of failing
+    pub wait: bool,
+    /// Limits the scan of the local side to these paths. Usually given by a `LocalWatcher`
+    pub changes: Option<HashSet<String>>
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        return SyncOptions{
+            batch_size: 8, retry: RetryPolicy::default(), timeout: None, cancel: CancelToken::new(), passphrase: None,
+            verify: false, wait: false, changes: None
+        }
+    }
+}
+
+/// Callbacks used by a session to interact with its frontend
+pub struct SyncCallbacks<'a> {
+    /// Asked for every new conflict before anything is transferred. Skipped conflicts are recorded
+    /// in the ledger and left untouched by the next syncs until resolved with `Session::resolve_conflict`
+    pub resolver: Box<dyn ConflictResolver + 'a>,
+    /// Called on the calling thread for every event of the sync
+    pub on_event: Box<dyn FnMut(SyncEvent) + 'a>
+}
+
+impl Default for SyncCallbacks<'_> {
+    /// Defers every conflict and ignores the events
+    fn default() -> Self {
+        return SyncCallbacks{ resolver: Box::new(StaticResolver(Resolution::Skip)), on_event: Box::new(|_| {}) }
+    }
+}
+
+/// Result of adopting a link
+#[derive(Debug, Clone, Default)]
+pub struct AdoptionReport {
+    /// Number of identical files recorded in the ledger
+    pub adopted: usize,
+    /// Files present on both sides with differing content
+    pub differing: Vec<String>,
+    /// Result of the sync that followed the adoption, if any
+    pub sync: Option<SyncReport>
+}
+
+/// Entry point of the library to sync links. A session can run any number of syncs, concurrently
+/// from different threads if needed, with the same semantics as the CLI
+pub struct Session {
+    engine: SyncEngine
+}
+
+impl Session {
+    pub fn new(thread_nb: usize) -> Self {
+        return Session{ engine: SyncEngine::new(thread_nb) }
+    }
+
+    pub fn engine(&self) -> &SyncEngine {
+        return &self.engine;
+    }
+
+    /// Syncs a link: lists both sides, compares them with the ledger, lets `callbacks` resolve the
+    /// conflicts, applies the actions and saves the new ledger. Fails with `ErrorKind::Interrupted`
+    /// if the resolver aborts the sync
+    pub fn sync(&self, link: &Config, options: &SyncOptions, callbacks: &mut SyncCallbacks) -> Result<SyncReport, Error> {
+        let remote_backend = Self::remote_backend(link, options)?;
+        return self.sync_with(link, remote_backend.as_ref(), options, callbacks);
+    }
+
+    /// Same as `sync` but against `remote_backend` instead of the remote of the link
+    pub fn sync_with(&self, link: &Config, remote_backend: &dyn Backend, options: &SyncOptions, callbacks: &mut SyncCallbacks) -> Result<SyncReport, Error> {
+        let _lock = Self::lock(link, options.wait)?;
+        let started = OffsetDateTime::now_utc();
+
+        let mut ledger = Ledger::load(&link.link_path);
+        let local_backend = LocalBackend::new(&link.local);
+
+        (callbacks.on_event)(SyncEvent::ListingStarted(Side::Local));
+        let mut scanner = LocalScanner::load(&link.local, &link.link_path);
+        let previous = scanner.list();
+        let local = self.engine.install(|| match &options.changes {
+            None => scanner.scan(),
+            Some(changes) => scanner.scan_paths(changes)
+        })?;
+        scanner.save(&link.link_path);
+        (callbacks.on_event)(SyncEvent::ListingFinished(Side::Local, local.len()));
+
+        let remote = Self::list_remote(remote_backend, false, callbacks)?;
+
+        let mut actions = gen_action_list(&local, &remote, &ledger);
+        Self::move_renamed(link, remote_backend, &mut ledger, &mut actions, &previous, &local);
+        return self.apply(link, (&local_backend, remote_backend), ledger, actions, &local, &remote, started, false, options, callbacks);
+    }
+
+    /// Actions the next sync of a link would apply, conflicts included, sorted by path. Nothing is
+    /// transferred and neither the ledger nor the scan cache is modified
+    pub fn status(&self, link: &Config, options: &SyncOptions) -> Result<Vec<Action>, Error> {
+        let ledger = Ledger::load(&link.link_path);
+        let (_, remote_backend) = Self::backends(link, options)?;
+
+        let mut scanner = LocalScanner::load(&link.local, &link.link_path);
+        let local = scanner.scan()?;
+        let remote = remote_backend.list(false)?;
+
+        let mut actions = gen_action_list(&local, &remote, &ledger).into_iter()
+            .filter(|a| a.action != ActionType::Nothing)
+            .collect::<Vec<Action>>();
+        actions.sort_by(|a, b| a.path.cmp(&b.path));
+        return Ok(actions);
+    }
+
+    /// Both versions of a file of a link, with their md5 and sha1 hashes, or `None` for the sides
+    /// without the file. The remote version is downloaded to `dest` so it can be compared
+    pub fn fetch(&self, link: &Config, path: &str, dest: &Path, options: &SyncOptions) -> Result<(Option<RFileInfo>, Option<RFileInfo>), Error> {
+        let (local_backend, remote_backend) = Self::backends(link, options)?;
+
+        let mut local = local_backend.stat(path)?.filter(|f| !f.is_dir);
+        if let Some(file) = &mut local { file.hashes = local_backend.hash(path)?; }
+
+        let mut remote = remote_backend.stat(path)?.filter(|f| !f.is_dir);
+        if let Some(file) = &mut remote {
+            io::copy(&mut remote_backend.read(path)?, &mut File::create(dest)?)?;
+            file.hashes = hash_content(&mut File::open(dest)?)?;
+        }
+        return Ok((local, remote));
+    }
+
+    /// Rebuilds the ledger of a link from scratch by comparing both sides by size and hash.
+    /// Identical files are recorded straight into the ledger. Differing files are resolved with
+    /// `prefer`, or handled as conflicts if it is not set. If `sync` is set, the remaining
+    /// actions are applied as a normal sync would
+    pub fn adopt(&self, link: &Config, prefer: Option<Preference>, sync: bool, options: &SyncOptions, callbacks: &mut SyncCallbacks) -> Result<AdoptionReport, Error> {
+        let _lock = Self::lock(link, options.wait)?;
+        let started = OffsetDateTime::now_utc();
+
+        let (local_backend, remote_backend) = Self::backends(link, options)?;
+
+        (callbacks.on_event)(SyncEvent::ListingStarted(Side::Local));
+        let local = self.engine.install(|| local_backend.list(true))?;
+        (callbacks.on_event)(SyncEvent::ListingFinished(Side::Local, local.len()));
+        let remote = Self::list_remote(remote_backend.as_ref(), true, callbacks)?;
+
+        let (actions, ledger) = gen_adoption_list(&local, &remote, prefer);
+        ledger.save(&link.link_path);
+
+        // files present on both sides that did not make it into the ledger have differing content
+        let remote_paths = remote.iter().map(|r| &r.path).collect::<HashSet<&String>>();
+        let mut differing = local.iter()
+            .filter(|l| !l.is_dir && remote_paths.contains(&l.path) && !ledger.path_map.contains_key(&l.path))
+            .map(|l| l.path.clone())
+            .collect::<Vec<String>>();
+        differing.sort();
+
+        let adopted = ledger.path_map.len();
+        let sync = match sync {
+            true => Some(self.apply(link, (local_backend.as_ref(), remote_backend.as_ref()), ledger, actions, &local, &remote, started, false, options, callbacks)?),
+            false => None
+        };
+        return Ok(AdoptionReport{ adopted, differing, sync });
+    }
+
+    /// Resolves a conflict deferred by a past sync and applies the resolution. Only the file of the
+    /// conflict is looked at, the other files are left for the next sync
+    pub fn resolve_conflict(&self, link: &Config, path: &str, resolution: Resolution, options: &SyncOptions, on_event: &mut dyn FnMut(SyncEvent)) -> Result<SyncReport, Error> {
+        let _lock = Self::lock(link, options.wait)?;
+        let started = OffsetDateTime::now_utc();
+
+        let mut ledger = Ledger::load(&link.link_path);
+        if !ledger.conflicts.contains_key(path) {
+            return Err(Error::new(ErrorKind::NotFound, format!("no conflict recorded for {path}")));
+        }
+
+        let (local_backend, remote_backend) = Self::backends(link, options)?;
+        let local = local_backend.stat(path)?.filter(|f| !f.is_dir).into_iter().collect::<Vec<RFileInfo>>();
+        let remote = remote_backend.stat(path)?.filter(|f| !f.is_dir).into_iter().collect::<Vec<RFileInfo>>();
+
+        // the conflict is taken from the current state of the file, which may have changed since it was recorded
+        let known = ledger.path_map.contains_key(path);
+        let conflict = match (local.is_empty(), remote.is_empty()) {
+            (false, false) if known => ErrorType::TwoSideMod,
+            (false, false) => ErrorType::TwoNew,
+            (false, true) => ErrorType::ModAndDel,
+            (true, false) => ErrorType::DelAndMod,
+            // the file is gone from both sides, so is the conflict
+            (true, true) => {
+                ledger.conflicts.remove(path);
+                ledger.path_map.remove(path);
+                ledger.save(&link.link_path);
+                return Ok(SyncReport::default());
+            }
+        };
+        let actions = vec![Action::new(path, ActionType::Error(conflict))];
+
+        let mut callbacks = SyncCallbacks{ resolver: Box::new(StaticResolver(resolution)), on_event: Box::new(on_event) };
+        return self.apply(link, (local_backend.as_ref(), remote_backend.as_ref()), ledger, actions, &local, &remote, started, true, options, &mut callbacks);
+    }
+
+    fn lock(link: &Config, wait: bool) -> Result<SyncLock, Error> {
+        return match SyncLock::acquire(&link.link_path) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock && wait => SyncLock::wait(&link.link_path),
+            lock => lock
+        }
+    }
+
+    /// Backends of the local and remote sides of a link
+    fn backends(link: &Config, options: &SyncOptions) -> Result<Backends, Error> {
+        return Ok((Box::new(LocalBackend::new(&link.local)), Self::remote_backend(link, options)?));
+    }
+
+    /// Backend of the remote side of a link. The remote of encrypted links is wrapped in their
+    /// crypt layer
+    #[cfg(any(feature = "rclone", feature = "rcd"))]
+    fn remote_backend(link: &Config, options: &SyncOptions) -> Result<Box<dyn Backend>, Error> {
+        let mut remote = match &link.rcd {
+            #[cfg(feature = "rcd")]
+            Some(server) => RCloneBackend::with_server(&link.remote, RcdClient::new(server)),
+            #[cfg(not(feature = "rcd"))]
+            Some(_) => { return Err(Error::new(ErrorKind::Unsupported, "this link uses an rclone server but warp is built without the rcd feature")); }
+            #[cfg(feature = "rclone")]
+            None => RCloneBackend::new(&link.remote),
+            #[cfg(not(feature = "rclone"))]
+            None => { return Err(Error::new(ErrorKind::Unsupported, "this link uses the built-in rclone but warp is built without the rclone feature")); }
+        };
+
+        if let Some(crypt) = &link.crypt {
+            let password = crypt.password(&link.link_path, options.passphrase.as_deref())?;
+            remote = remote.encrypted(&password, &crypt.salt)?;
+        }
+        return Ok(Box::new(remote));
+    }
+
+    /// Without rclone, the remote must be a local folder
+    #[cfg(not(any(feature = "rclone", feature = "rcd")))]
+    fn remote_backend(link: &Config, _options: &SyncOptions) -> Result<Box<dyn Backend>, Error> {
+        if link.rcd.is_some() || link.crypt.is_some() {
+            return Err(Error::new(ErrorKind::Unsupported, "this link needs rclone but warp is built without it"));
+        }
+        return Ok(Box::new(LocalBackend::new(&link.remote)));
+    }
+
+    /// Detects the local files renamed since the last scan, which show up as the deletion of a
+    /// synced file and a new file that is the same file on disk, unmodified. The remote file is
+    /// moved instead of being deleted and uploaded again. If the move fails, both actions are kept
+    fn move_renamed(link: &Config, remote_backend: &dyn Backend, ledger: &mut Ledger, actions: &mut Vec<Action>, previous: &[RFileInfo], local: &[RFileInfo]) {
+        let previous = previous.iter().map(|f| (&f.path, f)).collect::<HashMap<&String, &RFileInfo>>();
+        let local = local.iter().map(|f| (&f.path, f)).collect::<HashMap<&String, &RFileInfo>>();
+        let mut new_files = actions.iter()
+            .filter(|a| a.action == ActionType::Local2Remote && !ledger.path_map.contains_key(&a.path))
+            .filter_map(|a| local.get(&a.path).copied())
+            .collect::<Vec<&RFileInfo>>();
+
+        let mut renames = Vec::new();
+        for action in actions.iter().filter(|a| a.action == ActionType::DelRemote) {
+            let Some(old) = previous.get(&action.path) else { continue; };
+            let Some(i) = new_files.iter().position(|new| old.same_file(new) && old.size == new.size && old.mod_time == new.mod_time) else { continue; };
+            renames.push((action.path.clone(), new_files.swap_remove(i).path.clone()));
+        }
+
+        for (from, to) in renames {
+            if remote_backend.move_file(&from, &to).is_err() { continue; }
+            // recorded right away, the remote file is already moved
+            ledger.path_map.remove(&from);
+            ledger.path_map.insert(to.clone(), LedgerInfo::new(OffsetDateTime::now_utc()));
+            ledger.save(&link.link_path);
+
+            actions.retain(|a| a.path != from);
+            if let Some(action) = actions.iter_mut().find(|a| a.path == to) { action.action = ActionType::Nothing; }
+        }
+    }
+
+    fn list_remote(remote: &dyn Backend, hashes: bool, callbacks: &mut SyncCallbacks) -> Result<Vec<RFileInfo>, Error> {
+        (callbacks.on_event)(SyncEvent::ListingStarted(Side::Remote));
+        let remote = remote.list(hashes)?;
+        (callbacks.on_event)(SyncEvent::ListingFinished(Side::Remote, remote.len()));
+        return Ok(remote);
+    }
+
+    /// Resolves the conflicts, applies the actions and records the sync in the history of the link.
+    /// A `partial` sync only covers the files of `actions`, the ledger entries of the other files are kept
+    #[allow(clippy::too_many_arguments)]
+    fn apply(&self, link: &Config, (local_backend, remote_backend): (&dyn Backend, &dyn Backend), mut ledger: Ledger, mut actions: Vec<Action>, local: &[RFileInfo], remote: &[RFileInfo], started: OffsetDateTime, partial: bool, options: &SyncOptions, callbacks: &mut SyncCallbacks) -> Result<SyncReport, Error> {
+        let mut remote = remote.to_vec();
+        let (renamed, conflicts) = Self::resolve(remote_backend, &ledger, &mut actions, local, &mut remote, partial, callbacks)?;
+        let remote = &remote;
+
+        // nothing was transferred yet, the ledger is left as it is
+        if options.cancel.is_cancelled() {
+            return Err(Error::new(ErrorKind::Interrupted, "sync cancelled"));
+        }
+
+        // deferred conflicts are not transferred and keep their previous ledger entry
+        let to_apply = actions.iter()
+            .filter(|a| a.action != ActionType::Nothing && !a.action.is_error())
+            .cloned()
+            .collect::<Vec<Action>>();
+        // conflicts whose rename failed are only reported as failed
+        let deferred = actions.iter()
+            .filter(|a| a.action.is_error() && !renamed.iter().any(|f| f.action.path == a.path))
+            .cloned()
+            .collect::<Vec<Action>>();
+
+        (callbacks.on_event)(SyncEvent::Planned{ actions: to_apply.len(), bytes: Self::transfer_size(&to_apply, local, remote) });
+
+        let options = &SyncOptions{ verify: options.verify || link.verify, ..options.clone() };
+        let (tx, rx) = mpsc::channel();
+        let mut report = thread::scope(|s| {
+            let engine = s.spawn(|| self.engine.apply(local_backend, remote_backend, &to_apply, Some(tx), options));
+
+            let mut verified = HashMap::new();
+            for event in rx {
+                // files are only recorded once transferred, with their hashes if they were verified
+                match &event {
+                    SyncEvent::ActionVerified{ path, hashes } => { verified.insert(path.clone(), hashes.clone()); }
+                    SyncEvent::ActionFinished(action) => {
+                        ledger.update_ledger(&action.path, action.action.clone());
+                        if let Some(hashes) = verified.remove(&action.path) { ledger.set_hashes(&action.path, hashes); }
+                        ledger.save(&link.link_path);
+                    }
+                    _ => {}
+                }
+                (callbacks.on_event)(event);
+            }
+
+            return engine.join().unwrap();
+        });
+
+        let new_ledger = Ledger::ledger_from(&actions, &ledger, &report);
+        let new_ledger = if partial { ledger.merge(new_ledger, &actions) } else { new_ledger };
+        new_ledger.save(&link.link_path);
+
+        report.deferred = deferred;
+        report.failed.extend(renamed);
+
+        let bytes = Self::transfer_size(&report.applied, local, remote);
+        // the history is informative only, failing to write it does not fail the sync
+        let _ = HistoryEntry::new(started, &report, bytes, &conflicts).append(&link.link_path);
+        return Ok(report);
+    }
+
+    /// Asks the resolver about every conflict. Unless the sync is `partial`, the conflicts recorded
+    /// in the ledger are not asked about and stay deferred. Keeping both versions of a file renames the remote
+    /// one right away, the renamed file is then downloaded and the local one uploaded. Returns the
+    /// renames that failed, whose conflict stays recorded but is only reported as failed, and the conflicts
+    /// with their resolution
+    fn resolve(remote_backend: &dyn Backend, ledger: &Ledger, actions: &mut Vec<Action>, local: &[RFileInfo], remote: &mut [RFileInfo], partial: bool, callbacks: &mut SyncCallbacks) -> Result<(Vec<FailedAction>, Resolved), Error> {
+        let local_files = local.iter().map(|f| (&f.path, f)).collect::<HashMap<&String, &RFileInfo>>();
+        let mut resolutions = Vec::new();
+        {
+            let remote_files = remote.iter().map(|f| (&f.path, f)).collect::<HashMap<&String, &RFileInfo>>();
+            for (i, action) in actions.iter().enumerate().filter(|(_, a)| a.action.is_error() && (partial || !ledger.conflicts.contains_key(&a.path))) {
+                let resolution = callbacks.resolver.resolve(
+                    action,
+                    local_files.get(&action.path).copied(),
+                    remote_files.get(&action.path).copied(),
+                    ledger.path_map.get(&action.path)
+                );
+                if resolution == Resolution::Abort {
+                    return Err(Error::new(ErrorKind::Interrupted, "sync aborted while resolving conflicts"));
+                }
+                resolutions.push((i, resolution));
+            }
+        }
+
+        let conflicts = resolutions.iter().map(|(i, resolution)| (actions[*i].clone(), *resolution)).collect();
+        let mut failed = Vec::new();
+        let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+        for (i, resolution) in resolutions {
+            let action = &mut actions[i];
+            match resolution {
+                Resolution::KeepLocal => { action.keep_local(); }
+                Resolution::KeepRemote => { action.keep_remote(); }
+                // only one version is left when the conflict involves a deletion
+                Resolution::KeepBoth if !action.needs_rename() => {
+                    if local_files.contains_key(&action.path) { action.keep_local(); } else { action.keep_remote(); }
+                }
+                Resolution::KeepBoth => {
+                    let new_path = conflict_name(&action.path, timestamp);
+                    match remote_backend.move_file(&action.path, &new_path) {
+                        Err(e) => { failed.push(FailedAction{ action: action.clone(), error: e.to_string() }); }
+                        Ok(()) => {
+                            action.keep_local();
+                            if let Some(file) = remote.iter_mut().find(|f| f.path == action.path) { file.path = new_path.clone(); }
+                            actions.push(Action::new(&new_path, ActionType::Remote2Local));
+                        }
+                    }
+                }
+                Resolution::Skip | Resolution::Abort => {}
+            }
+        }
+        return Ok((failed, conflicts));
+    }
+
+    /// Sum of the sizes of the files to copy
+    fn transfer_size(actions: &[Action], local: &[RFileInfo], remote: &[RFileInfo]) -> u64 {
+        let local_files = local.iter().map(|f| (&f.path, f)).collect::<HashMap<&String, &RFileInfo>>();
+        let remote_files = remote.iter().map(|f| (&f.path, f)).collect::<HashMap<&String, &RFileInfo>>();
+
+        return actions.iter().filter_map(|a| match a.action {
+            ActionType::Local2Remote => local_files.get(&a.path),
+            ActionType::Remote2Local => remote_files.get(&a.path),
+            _ => None
+        }).map(|f| f.size.max(0) as u64).sum();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read, rename, write};
+    use super::*;
+    use crate::backend::{MemoryBackend, TransferContext};
+    use crate::test_utils::{FailingBackend, TempDir};
+
+    /// Link between `local` and a remote given to `Session::sync_with`, with an empty ledger
+    fn link_of(local: &TempDir, link_path: &TempDir) -> Config {
+        Ledger::new().save(link_path.str());
+        return Config{
+            link_path: link_path.str().to_string(), local: local.str().to_string(), remote: String::new(),
+            update_rt: 0, rcd: None, crypt: None, verify: false
+        };
+    }
+
+    #[test]
+    fn sync_propagates_changes_both_ways() {
+        let (local, link_path) = (TempDir::new(), TempDir::new());
+        let link = link_of(&local, &link_path);
+        let remote = MemoryBackend::new();
+        let session = Session::new(2);
+
+        write(local.path().join("local.txt"), "local").unwrap();
+        remote.insert("dir/remote.txt", b"remote", OffsetDateTime::UNIX_EPOCH);
+
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.applied.len(), 2);
+        assert_eq!(remote.content("local.txt").unwrap(), b"local");
+        assert_eq!(read(local.path().join("dir/remote.txt")).unwrap(), b"remote");
+
+        // nothing changed since
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+        assert!(report.applied.is_empty());
+
+        write(local.path().join("local.txt"), "modified").unwrap();
+        remote.delete(&["dir/remote.txt".to_string()], &TransferContext{ group: String::new(), timeout: None, cancel: CancelToken::new() }).unwrap();
+
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+        assert_eq!(report.applied.len(), 2);
+        assert_eq!(remote.content("local.txt").unwrap(), b"modified");
+        assert!(!local.path().join("dir/remote.txt").exists());
+        assert_eq!(Ledger::load(link_path.str()).path_map.keys().collect::<Vec<&String>>(), vec!["local.txt"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn renamed_files_are_moved_on_the_remote() {
+        let (local, link_path) = (TempDir::new(), TempDir::new());
+        let link = link_of(&local, &link_path);
+        let remote = MemoryBackend::new();
+        let session = Session::new(2);
+
+        write(local.path().join("old.txt"), "content").unwrap();
+        session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+
+        create_dir_all(local.path().join("dir")).unwrap();
+        rename(local.path().join("old.txt"), local.path().join("dir/new.txt")).unwrap();
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+
+        // nothing was transferred, the remote file was moved
+        assert!(report.applied.is_empty());
+        assert_eq!(remote.content("old.txt"), None);
+        assert_eq!(remote.content("dir/new.txt").unwrap(), b"content");
+        assert_eq!(Ledger::load(link_path.str()).path_map.keys().collect::<Vec<&String>>(), vec!["dir/new.txt"]);
+
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+        assert!(report.applied.is_empty());
+    }
+
+    #[test]
+    fn deferred_conflict_survives_the_next_sync() {
+        let (local, link_path) = (TempDir::new(), TempDir::new());
+        let link = link_of(&local, &link_path);
+        let remote = MemoryBackend::new();
+        let session = Session::new(2);
+
+        write(local.path().join("both.txt"), "local").unwrap();
+        remote.insert("both.txt", b"remote", OffsetDateTime::UNIX_EPOCH);
+
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut SyncCallbacks::default()).unwrap();
+        assert_eq!(report.deferred.len(), 1);
+        assert!(Ledger::load(link_path.str()).conflicts.contains_key("both.txt"));
+
+        // the recorded conflict is not asked about again, so it is not resolved by the next sync
+        let mut callbacks = SyncCallbacks{ resolver: Box::new(StaticResolver(Resolution::KeepLocal)), ..SyncCallbacks::default() };
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut callbacks).unwrap();
+        assert!(report.applied.is_empty());
+        assert_eq!(report.deferred.iter().map(|a| a.action.clone()).collect::<Vec<ActionType>>(), vec![ActionType::Error(ErrorType::TwoNew)]);
+        assert_eq!(remote.content("both.txt").unwrap(), b"remote");
+        assert_eq!(read(local.path().join("both.txt")).unwrap(), b"local");
+        assert!(Ledger::load(link_path.str()).conflicts.contains_key("both.txt"));
+    }
+
+    #[test]
+    fn failed_rename_is_only_reported_as_failed() {
+        let (local, link_path) = (TempDir::new(), TempDir::new());
+        let link = link_of(&local, &link_path);
+        let remote = FailingBackend{ fail_moves: true, ..FailingBackend::default() };
+        let session = Session::new(2);
+
+        write(local.path().join("both.txt"), "local").unwrap();
+        remote.inner.insert("both.txt", b"remote", OffsetDateTime::UNIX_EPOCH);
+
+        let mut callbacks = SyncCallbacks{ resolver: Box::new(StaticResolver(Resolution::KeepBoth)), ..SyncCallbacks::default() };
+        let report = session.sync_with(&link, &remote, &SyncOptions::default(), &mut callbacks).unwrap();
+
+        assert_eq!(report.failed.iter().map(|f| f.action.path.as_str()).collect::<Vec<&str>>(), vec!["both.txt"]);
+        assert!(report.deferred.is_empty());
+        assert!(report.applied.is_empty());
+        assert_eq!(remote.inner.content("both.txt").unwrap(), b"remote");
+        // the conflict is still recorded, to be resolved later
+        assert!(Ledger::load(link_path.str()).conflicts.contains_key("both.txt"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::io::{Error, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use time::OffsetDateTime;
+use crate::backend::{Backend, MemoryBackend, RFileInfo, TransferContext};
+use crate::engine::CancelToken;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Empty folder in the system temp dir, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let name = format!("warp-test-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let _ = remove_dir_all(&path);
+        create_dir_all(&path).unwrap();
+        return TempDir(path);
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.0;
+    }
+
+    pub fn str(&self) -> &str {
+        return self.0.to_str().unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}
+
+/// Memory backend failing to write some files, a given number of times each, and to move files if
+/// `fail_moves` is set. It can also cancel the sync once it wrote a file
+#[derive(Default)]
+pub struct FailingBackend {
+    pub inner: MemoryBackend,
+    pub failures: Mutex<HashMap<String, usize>>,
+    pub cancel: Option<CancelToken>,
+    pub fail_moves: bool
+}
+
+impl FailingBackend {
+    pub fn failing(paths: &[(&str, usize)]) -> Self {
+        let failures = paths.iter().map(|(path, times)| (path.to_string(), *times)).collect();
+        return FailingBackend{ failures: Mutex::new(failures), ..FailingBackend::default() };
+    }
+}
+
+impl Backend for FailingBackend {
+    fn list(&self, hashes: bool) -> Result<Vec<RFileInfo>, Error> { return self.inner.list(hashes); }
+    fn stat(&self, path: &str) -> Result<Option<RFileInfo>, Error> { return self.inner.stat(path); }
+    fn hash(&self, path: &str) -> Result<HashMap<String, String>, Error> { return self.inner.hash(path); }
+    fn read(&self, path: &str) -> Result<Box<dyn Read + Send>, Error> { return self.inner.read(path); }
+
+    fn write(&self, path: &str, content: &mut dyn Read, mod_time: OffsetDateTime) -> Result<(), Error> {
+        if let Some(left) = self.failures.lock().unwrap().get_mut(path).filter(|left| **left > 0) {
+            *left -= 1;
+            return Err(Error::other(format!("unable to write {path}")));
+        }
+        self.inner.write(path, content, mod_time)?;
+        if let Some(cancel) = &self.cancel { cancel.cancel(); }
+        return Ok(());
+    }
+
+    fn delete(&self, files: &[String], ctx: &TransferContext) -> Result<(), Error> { return self.inner.delete(files, ctx); }
+
+    fn move_file(&self, from: &str, to: &str) -> Result<(), Error> {
+        if self.fail_moves { return Err(Error::other(format!("unable to move {from}"))); }
+        return self.inner.move_file(from, to);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::io::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+#[derive(Default)]
+struct WatchState {
+    dirty: HashSet<String>,
+    last_event: Option<Instant>
+}
+
+/// Watches the local folder of a link and accumulates the paths changed in it. Paths are relative
+/// to the watched folder and use the same format as `RFileInfo::path`
+pub struct LocalWatcher {
+    state: Arc<Mutex<WatchState>>,
+    _watcher: RecommendedWatcher
+}
+
+impl LocalWatcher {
+    pub fn new(local: &str) -> Result<Self, Error> {
+        let root = Path::new(local).canonicalize()?;
+        let state = Arc::new(Mutex::new(WatchState::default()));
+
+        let _state = state.clone();
+        let _root = root.clone();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let event = match res { Ok(event) => event, Err(_) => return };
+            if matches!(event.kind, EventKind::Access(_)) { return; }
+
+            let mut state = _state.lock().unwrap();
+            state.dirty.extend(event.paths.iter().filter_map(|p| relative_path(&_root, p)));
+            state.last_event = Some(Instant::now());
+        }).map_err(Error::other)?;
+
+        watcher.watch(&root, RecursiveMode::Recursive).map_err(Error::other)?;
+        return Ok(LocalWatcher{ state, _watcher: watcher })
+    }
+
+    /// Returns the paths changed since the last call once no event was received for `debounce`.
+    /// Returns `None` if nothing changed or if changes are still happening
+    pub fn settled(&self, debounce: Duration) -> Option<HashSet<String>> {
+        let mut state = self.state.lock().unwrap();
+        match state.last_event {
+            Some(last) if last.elapsed() >= debounce => {
+                state.last_event = None;
+                Some(std::mem::take(&mut state.dirty))
+            }
+            _ => None
+        }
+    }
+
+    /// Forgets the changes of `paths` and of their parent directories. Used after a sync to ignore
+    /// the changes it made itself, while keeping the ones made by the user in the meantime
+    pub fn forget(&self, paths: &HashSet<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.dirty.retain(|dirty| !paths.iter().any(|path| path == dirty || path.starts_with(&format!("{dirty}/"))));
+        if state.dirty.is_empty() { state.last_event = None; }
+    }
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts = relative.components().map(|c| c.as_os_str().to_str()).collect::<Option<Vec<&str>>>()?;
+    if parts.is_empty() { return None; }
+    return Some(parts.join("/"));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+    use std::thread;
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    /// Waits for the events of `paths` to be received
+    fn wait_for(watcher: &LocalWatcher, paths: &[&str]) {
+        for _ in 0..200 {
+            if paths.iter().all(|p| watcher.state.lock().unwrap().dirty.contains(*p)) { return; }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no event received for {paths:?}");
+    }
+
+    #[test]
+    fn forget_keeps_the_other_changes() {
+        let dir = TempDir::new();
+        create_dir(dir.path().join("dir")).unwrap();
+        let watcher = LocalWatcher::new(dir.str()).unwrap();
+
+        // written by a sync while the user edits another file
+        write(dir.path().join("dir/synced.txt"), "synced").unwrap();
+        write(dir.path().join("edited.txt"), "edited").unwrap();
+        wait_for(&watcher, &["dir/synced.txt", "edited.txt"]);
+
+        watcher.forget(&HashSet::from(["dir/synced.txt".to_string()]));
+        assert_eq!(watcher.settled(Duration::ZERO), Some(HashSet::from(["edited.txt".to_string()])));
+    }
+
+    #[test]
+    fn forgetting_every_change_settles_nothing() {
+        let dir = TempDir::new();
+        let watcher = LocalWatcher::new(dir.str()).unwrap();
+
+        write(dir.path().join("synced.txt"), "synced").unwrap();
+        wait_for(&watcher, &["synced.txt"]);
+
+        watcher.forget(&HashSet::from(["synced.txt".to_string()]));
+        assert_eq!(watcher.settled(Duration::ZERO), None);
+    }
+}
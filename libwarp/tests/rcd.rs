@@ -0,0 +1,95 @@
+//! Tests against a real `rclone rcd` server. They need the rclone binary (`RCLONE`, or `rclone`
+//! from the PATH) and are ignored by default: `cargo test -p warp --features rcd -- --ignored`
+#![cfg(feature = "rcd")]
+
+use std::fs;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use warp::backend::Backend;
+use warp::configs::RcdConfig;
+use warp::rcd::RcdClient;
+use warp::rclone::RCloneBackend;
+
+const USER: &str = "warp";
+const PASS: &str = "secret";
+
+/// An `rclone rcd` server with authentication, and a local folder used as remote. Both are
+/// removed on drop
+struct Server {
+    process: Child,
+    url: String,
+    root: PathBuf
+}
+
+impl Server {
+    fn start(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("warp-rcd-{}-{name}", std::process::id()));
+        fs::create_dir_all(root.join("data")).unwrap();
+
+        // a free port, released just before rclone takes it
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let rclone = std::env::var("RCLONE").unwrap_or("rclone".to_string());
+        let process = Command::new(rclone)
+            .args(["rcd", "--rc-addr", &format!("127.0.0.1:{port}"), "--rc-user", USER, "--rc-pass", PASS])
+            .arg("--config").arg(root.join("rclone.conf"))
+            .stdout(Stdio::null()).stderr(Stdio::null())
+            .spawn().expect("rclone binary not found");
+
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "rclone rcd did not start");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        return Server{ process, url: format!("http://127.0.0.1:{port}"), root };
+    }
+
+    fn backend(&self, pass: &str) -> RCloneBackend {
+        let config = RcdConfig{ url: self.url.clone(), user: Some(USER.to_string()), pass: Some(pass.to_string()) };
+        return RCloneBackend::with_server(self.root.join("data").to_str().unwrap(), RcdClient::new(&config));
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[test]
+#[ignore]
+fn wrong_password_is_rejected() {
+    let server = Server::start("auth");
+    assert!(server.backend("wrong").list(false).is_err());
+    assert!(server.backend(PASS).list(false).is_ok());
+}
+
+#[test]
+#[ignore]
+fn files_are_written_listed_and_read() {
+    let server = Server::start("files");
+    let backend = server.backend(PASS);
+
+    backend.write("dir/a.txt", &mut "hello".as_bytes(), OffsetDateTime::now_utc()).unwrap();
+    assert_eq!(fs::read_to_string(server.root.join("data/dir/a.txt")).unwrap(), "hello");
+
+    fs::write(server.root.join("data/b.txt"), "from the remote").unwrap();
+    let mut files = backend.list(false).unwrap().into_iter()
+        .filter(|f| !f.is_dir)
+        .map(|f| f.path)
+        .collect::<Vec<String>>();
+    files.sort();
+    assert_eq!(files, ["b.txt", "dir/a.txt"]);
+
+    let mut content = String::new();
+    backend.read("b.txt").unwrap().read_to_string(&mut content).unwrap();
+    assert_eq!(content, "from the remote");
+    assert!(backend.read("missing.txt").is_err());
+}
@@ -1,3 +1,91 @@
+use std::env;
+use std::io::{stdin, stdout, ErrorKind, IsTerminal, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use clap::ValueEnum;
+use termion::input::TermRead;
+use warp::{action::Preference, configs::Config, engine::CancelToken};
+use crate::cmds::{CmdSync, Outcome, fatal, json_output};
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
 pub trait Cmd {
-    fn execute(&self);
-}
\ No newline at end of file
+    fn execute(&self) -> Outcome;
+}
+
+/// Forces the non-interactive mode, see `interactive`
+pub fn set_non_interactive() {
+    NON_INTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Checks if the user can be prompted and the terminal controlled. Never the case without a
+/// terminal, as under cron or systemd, with `--non-interactive` or with JSON output
+pub fn interactive() -> bool {
+    return !NON_INTERACTIVE.load(Ordering::Relaxed) && !json_output() && stdin().is_terminal() && stdout().is_terminal();
+}
+
+/// Config of the given name, or of the current folder if no name is given, with the password of
+/// its rclone server. Failing to find it stops the command
+pub fn config_of(name: &Option<String>) -> Result<Config, Outcome> {
+    let mut config = CmdSync::get_config(name).map_err(|_| match name {
+        None => fatal(ErrorKind::NotFound, "Invalid location.\nPlease specify a config name or be in an existing config location"),
+        Some(name) => fatal(ErrorKind::NotFound, &format!("Invalid config name: '{}'", name))
+    })?;
+    if let Some(rcd) = config.rcd.as_mut().filter(|rcd| rcd.user.is_some()) {
+        rcd.pass = env::var("WARP_RCD_PASS").ok()
+            .or_else(|| interactive().then(|| read_passphrase(&format!("Password of {}: ", rcd.url))).flatten());
+    }
+    return Ok(config);
+}
+
+/// Returns a token cancelled by the first SIGINT or SIGTERM received. The next one exits right away
+pub fn interrupt_token() -> CancelToken {
+    let token = CancelToken::new();
+    let handler_token = token.clone();
+    let received = AtomicUsize::new(0);
+
+    ctrlc::set_handler(move || {
+        if received.fetch_add(1, Ordering::Relaxed) > 0 { process::exit(130); }
+        eprintln!("\nStopping the transfers in progress, press Ctrl-C again to exit now");
+        handler_token.cancel();
+    }).expect("Unable to set the interrupt handler");
+
+    return token;
+}
+
+/// Asks for a passphrase on the terminal without echoing it
+pub fn read_passphrase(prompt: &str) -> Option<String> {
+    print!("{prompt}");
+    stdout().flush().unwrap();
+    let passphrase = stdin().read_passwd(&mut stdout()).ok().flatten();
+    println!();
+    return passphrase;
+}
+
+/// Passphrase of links encrypted with one, taken from `WARP_PASSPHRASE` or asked on the terminal
+/// when interactive
+pub fn passphrase_of(config: &Config) -> Option<String> {
+    if !config.crypt.as_ref().is_some_and(|crypt| crypt.passphrase) { return None; }
+    if let Ok(passphrase) = env::var("WARP_PASSPHRASE") { return Some(passphrase); }
+    if !interactive() { return None; }
+
+    return read_passphrase(&format!("Passphrase of {}: ", config.local));
+}
+
+/// Side to keep for files that differ when adopting pre-populated folders
+#[derive(ValueEnum, Clone, Copy)]
+pub enum PreferArg {
+    Local,
+    Remote,
+    Newest
+}
+
+impl From<PreferArg> for Preference {
+    fn from(value: PreferArg) -> Self {
+        match value {
+            PreferArg::Local =>  { Preference::Local }
+            PreferArg::Remote => { Preference::Remote }
+            PreferArg::Newest => { Preference::Newest }
+        }
+    }
+}
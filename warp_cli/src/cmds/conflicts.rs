@@ -0,0 +1,41 @@
+use clap::Args;
+use serde_json::{Value, json};
+use time::format_description::well_known::Rfc3339;
+use warp::ledger::Ledger;
+use crate::cmds::{Cmd, Outcome, config_of, json_output, print_json};
+
+#[derive(Args)]
+pub struct CmdConflicts {
+    /// Name of the config. If not set, the config of the current folder is used
+    name: Option<String>
+}
+
+
+impl Cmd for CmdConflicts {
+    fn execute(&self) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
+
+        let ledger = Ledger::load(&config.link_path);
+        let outcome = if ledger.conflicts.is_empty() { Outcome::Success } else { Outcome::Conflicts };
+        if json_output() {
+            print_json(Value::Array(ledger.conflicts.iter().map(|(path, info)| json!({
+                "path": path, "conflict": info.conflict, "since": info.since.format(&Rfc3339).unwrap_or_default()
+            })).collect()));
+            return outcome;
+        }
+
+        if ledger.conflicts.is_empty() {
+            println!("No conflict left unresolved");
+            return outcome;
+        }
+
+        println!("{} conflicts left unresolved, see `resolve` to settle them:", ledger.conflicts.len());
+        for (path, info) in &ledger.conflicts {
+            println!("    {path} ({}) since {}", info.conflict, info.since.format(&Rfc3339).unwrap_or_default());
+        }
+        return outcome;
+    }
+}
@@ -1,8 +1,11 @@
+use std::env;
+use std::io::ErrorKind;
 use clap::Args;
-use crate::cmds::Cmd;
+use serde_json::json;
+use crate::cmds::{Cmd, Outcome, PreferArg, fatal, interactive, json_output, print_json, read_passphrase};
 use crate::cmds::CmdSync;
 
-use warp::{ledger::Ledger, configs::Config};
+use warp::{ledger::Ledger, configs::{Config, CryptConfig, RcdConfig}, action::Preference};
 
 #[derive(Args)]
 pub struct CmdCreate {
@@ -13,25 +16,92 @@ pub struct CmdCreate {
     /// local path for the link config (ex: ~/location/). The default value is the curent folder
     #[arg(short, long, default_value="./")]
     local: String,
+    /// Interval in minutes between two syncs made by the daemon. If 0, the daemon's default is used
+    #[arg(short, long, default_value_t=0)]
+    update_rt: usize,
     /// If not set, the creation will also automatically sync
     #[arg(short, long, action=clap::ArgAction::SetTrue)]
-    no_sync: bool
+    no_sync: bool,
+    /// Adopts folders that already hold data: files identical on both sides are added to the ledger
+    /// instead of being reported as conflicts
+    #[arg(short, long, action=clap::ArgAction::SetTrue)]
+    adopt: bool,
+    /// When adopting, the side to keep for files that differ. If not set, they are handled as conflicts
+    #[arg(short, long, value_enum, requires="adopt", conflicts_with="no_sync")]
+    prefer: Option<PreferArg>,
+    /// Address of an `rclone rcd` server to reach the remote with, instead of the built-in rclone
+    /// (ex: http://localhost:5572). The remote must then be defined in the config of the server.
+    /// The server must run on this host, as files are exchanged with it through local temporary paths
+    #[arg(long)]
+    rcd: Option<String>,
+    /// User of the rclone server. Its password is not saved: it is taken from the WARP_RCD_PASS
+    /// environment variable, or asked when run interactively
+    #[arg(long, requires="rcd")]
+    rcd_user: Option<String>,
+    /// Encrypts the files and their names on the remote. Unless --passphrase is set, the key is
+    /// saved unencrypted in the crypt.key file of the link folder, readable by its owner only:
+    /// anyone able to read it can decrypt the remote
+    #[arg(short, long, action=clap::ArgAction::SetTrue)]
+    encrypt: bool,
+    /// Derives the key from a passphrase asked on every sync instead of keeping it on disk. The
+    /// WARP_PASSPHRASE environment variable is used instead of the prompt when set
+    #[arg(long, action=clap::ArgAction::SetTrue, requires="encrypt")]
+    passphrase: bool,
+    /// Checks the content of every transferred file on both sides, copying it again on mismatch
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    verify: bool
 }
 
 
 impl Cmd for CmdCreate {
-    fn execute(&self) {
+    fn execute(&self) -> Outcome {
         let names = Config::get_all_names();
         if names.contains(&self.name) {
-            panic!("Name {} already exists", &self.name)
+            return fatal(ErrorKind::AlreadyExists, &format!("Name {} already exists", &self.name));
         }
 
-        let configs = Config::new(&self.name, &self.local, &self.remote, 0);
+        let mut configs = Config::new(&self.name, &self.local, &self.remote, self.update_rt);
+        configs.verify = self.verify;
+        configs.rcd = self.rcd.as_ref().map(|url| RcdConfig{ url: url.clone(), user: self.rcd_user.clone(), pass: None });
+        let passphrase = match self.passphrase {
+            true => match Self::new_passphrase() {
+                Ok(passphrase) => Some(passphrase),
+                Err(e) => { return fatal(ErrorKind::InvalidInput, e); }
+            },
+            false => None
+        };
+        if self.encrypt {
+            match CryptConfig::generate(&configs.link_path, passphrase.as_deref()) {
+                Ok(crypt) => { configs.crypt = Some(crypt); }
+                Err(e) => { return fatal(e.kind(), &format!("Unable to create the encryption key: {e}")); }
+            }
+        }
         let ledger = Ledger::new();
 
         ledger.save(&configs.link_path);
         configs.save();
 
-        if !self.no_sync { CmdSync::new(&self.name).execute(); }
+        if self.adopt {
+            return CmdSync::new(Some(&self.name)).with_passphrase(passphrase).adopt(self.prefer.map(Preference::from), !self.no_sync);
+        }
+        if !self.no_sync {
+            return CmdSync::new(Some(&self.name)).with_passphrase(passphrase).execute();
+        }
+
+        if json_output() { print_json(json!({ "created": self.name })); }
+        return Outcome::Success;
     }
-}
\ No newline at end of file
+}
+
+impl CmdCreate {
+    /// Takes the passphrase from `WARP_PASSPHRASE`, or asks for it twice as a typo would make the
+    /// remote unreadable
+    fn new_passphrase() -> Result<String, &'static str> {
+        if let Ok(passphrase) = env::var("WARP_PASSPHRASE") { return Ok(passphrase); }
+        if !interactive() { return Err("The passphrase must be given with WARP_PASSPHRASE when not run interactively"); }
+
+        let passphrase = read_passphrase("Passphrase: ").ok_or("No passphrase given")?;
+        let confirmation = read_passphrase("Confirm the passphrase: ").ok_or("No passphrase given")?;
+        return if passphrase == confirmation { Ok(passphrase) } else { Err("The passphrases do not match") };
+    }
+}
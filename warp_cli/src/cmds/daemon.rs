@@ -0,0 +1,312 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::thread::{self, Scope, ScopedJoinHandle};
+use std::time::{Duration, Instant};
+use clap::Args;
+use serde_json::json;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use warp::{
+    action::{ActionType, Preference, SyncReport},
+    engine::{CancelToken, RetryPolicy}, configs::Config, conflict::{ConflictResolver, Resolution, StaticResolver},
+    event::SyncEvent, watch::LocalWatcher,
+    session::{Session, SyncCallbacks, SyncOptions}
+};
+use crate::cmds::{Cmd, Outcome, PreferArg, interrupt_token, json_output, print_json};
+
+#[derive(Args)]
+pub struct CmdDaemon {
+    /// Interval in minutes between two syncs for configs that do not define their own
+    #[arg(short, long, default_value_t=15)]
+    interval: usize,
+
+    /// The side to keep when a conflict is found. If not set, conflicting files are left untouched
+    /// and recorded, see the `conflicts` and `resolve` commands to settle them
+    #[arg(short, long, value_enum)]
+    on_conflict: Option<PreferArg>,
+
+    /// Disables watching the local folders. Local changes are then only found by the periodic syncs
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    no_watch: bool,
+
+    /// Delay in seconds without any local change before a watched folder is synced
+    #[arg(short, long, default_value_t=5)]
+    debounce: u64,
+
+    /// Defines the thread count
+    #[arg(short, long, default_value_t=4)]
+    thread_count: usize,
+
+    /// Defines the number of element to put in a single rclone request if run in parallel mode.
+    /// If 0 the maximum batch size will be used
+    #[arg(short, long, default_value_t=8)]
+    batch_size: usize,
+
+    /// Number of times a failing batch is retried before looking for the failing files
+    #[arg(short, long, default_value_t=3)]
+    retries: usize,
+
+    /// Delay in seconds before the first retry. The delay doubles after every retry
+    #[arg(long, default_value_t=1)]
+    retry_delay: u64,
+
+    /// Time in seconds after which a single transfer is stopped and retried. If 0, transfers have no time limit
+    #[arg(long, default_value_t=0)]
+    timeout: u64
+}
+
+
+/// State kept by the daemon for every config
+struct Link {
+    next_sync: Instant,
+    watcher: Option<LocalWatcher>
+}
+
+/// Outcome of the sync of a config, as needed to schedule the next one
+struct Synced {
+    /// Delay before the next periodic sync
+    interval: Duration,
+    /// Local paths written or deleted by the sync
+    written: HashSet<String>
+}
+
+/// Syncs in progress by config name
+type Running<'scope> = HashMap<String, ScopedJoinHandle<'scope, Synced>>;
+
+impl Cmd for CmdDaemon {
+    fn execute(&self) -> Outcome {
+        // time between two checks for new or deleted configs
+        let refresh_rate = Duration::from_secs(60);
+        let debounce = Duration::from_secs(self.debounce);
+        let mut links: HashMap<String, Link> = HashMap::new();
+        let mut last_refresh: Option<Instant> = None;
+        let session = Session::new(self.thread_count);
+        let cancel = interrupt_token();
+
+        let sync = |name: &str, changes: Option<HashSet<String>>| -> Synced {
+            if let Some(changes) = &changes {
+                Self::log(name, &format!("{} local changes detected", changes.len()));
+            }
+            return match Config::load(name) {
+                Err(e) => {
+                    Self::log(name, &format!("unable to load config: {e}"));
+                    Synced{ interval: self.interval_of(0), written: HashSet::new() }
+                }
+                Ok(mut config) => {
+                    if let Some(rcd) = &mut config.rcd { rcd.pass = env::var("WARP_RCD_PASS").ok(); }
+                    let written = match self.sync(&session, name, &config, changes.as_ref(), &cancel) {
+                        Ok(report) => {
+                            Self::log(name, &Self::summary(&report));
+                            report.applied.iter()
+                                .filter(|a| matches!(a.action, ActionType::Remote2Local | ActionType::DelLocal))
+                                .map(|a| a.path.clone())
+                                .collect()
+                        }
+                        Err(e) => {
+                            Self::log(name, &format!("sync failed: {e}"));
+                            HashSet::new()
+                        }
+                    };
+                    Synced{ interval: self.interval_of(config.update_rt), written }
+                }
+            };
+        };
+
+        Self::log("daemon", "started");
+        // the syncs still running once cancelled are waited for when leaving the scope
+        thread::scope(|s| {
+            let mut running = Running::new();
+            while !cancel.is_cancelled() {
+                if last_refresh.is_none_or(|last| last.elapsed() >= refresh_rate) {
+                    self.refresh(&mut links);
+                    last_refresh = Some(Instant::now());
+                }
+
+                schedule(s, &mut links, &mut running, debounce, &sync);
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+        Self::log("daemon", "stopped");
+        return Outcome::Success;
+    }
+}
+
+/// Collects the syncs that finished and starts the due ones. Every sync runs on its own thread, so a
+/// slow config does not hold back the others, and a config is never synced twice at the same time.
+/// A config is due when its interval elapsed or when the changes of its local folder settled
+fn schedule<'scope>(s: &'scope Scope<'scope, '_>, links: &mut HashMap<String, Link>, running: &mut Running<'scope>, debounce: Duration, sync: &'scope (dyn Fn(&str, Option<HashSet<String>>) -> Synced + Sync)) {
+    let finished = running.iter().filter(|(_, sync)| sync.is_finished()).map(|(name, _)| name.clone()).collect::<Vec<String>>();
+    for name in finished {
+        // panics are caught by the sync, a config whose thread still panicked is synced again right away
+        let Ok(synced) = running.remove(&name).unwrap().join() else { continue; };
+        let Some(link) = links.get_mut(&name) else { continue; };
+
+        // the changes made by the sync itself do not need to trigger another one, unlike the
+        // ones made by the user while it was running
+        if let Some(watcher) = &link.watcher { watcher.forget(&synced.written); }
+        link.next_sync = Instant::now() + synced.interval;
+    }
+
+    for (name, link) in links.iter_mut() {
+        if running.contains_key(name) { continue; }
+        let changes = link.watcher.as_ref().and_then(|w| w.settled(debounce));
+        if link.next_sync > Instant::now() && changes.is_none() { continue; }
+
+        let thread_name = name.clone();
+        running.insert(name.clone(), s.spawn(move || sync(&thread_name, changes)));
+    }
+}
+
+impl CmdDaemon {
+    /// Adds the configs created since the last refresh and forgets the deleted ones
+    fn refresh(&self, links: &mut HashMap<String, Link>) {
+        let names = Config::get_all_names();
+        links.retain(|name, _| names.contains(name));
+
+        for name in names {
+            if links.contains_key(&name) { continue; }
+
+            let watcher = match (self.no_watch, Config::load(&name)) {
+                (true, _) => None,
+                (false, Err(_)) => None,
+                (false, Ok(config)) => match LocalWatcher::new(&config.local) {
+                    Ok(watcher) => Some(watcher),
+                    Err(e) => {
+                        Self::log(&name, &format!("unable to watch local folder, falling back to periodic syncs: {e}"));
+                        None
+                    }
+                }
+            };
+            links.insert(name, Link{ next_sync: Instant::now(), watcher });
+        }
+    }
+
+    fn interval_of(&self, update_rt: usize) -> Duration {
+        let minutes = if update_rt == 0 { self.interval } else { update_rt };
+        return Duration::from_secs(60 * minutes as u64);
+    }
+
+    /// Syncs a config without ever prompting. Panics are caught so a failing config does not
+    /// stop the daemon
+    fn sync(&self, session: &Session, name: &str, config: &Config, changes: Option<&HashSet<String>>, cancel: &CancelToken) -> Result<SyncReport, String> {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.sync_config(session, name, config, changes, cancel)));
+
+        return match result {
+            Ok(res) => res,
+            Err(e) => {
+                let message = e.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| e.downcast_ref::<String>().cloned())
+                    .unwrap_or("unknown error".to_string());
+                Err(message)
+            }
+        }
+    }
+
+    /// When the sync is triggered by local changes, only the changed paths are scanned on the local side
+    fn sync_config(&self, session: &Session, name: &str, config: &Config, changes: Option<&HashSet<String>>, cancel: &CancelToken) -> Result<SyncReport, String> {
+        let options = SyncOptions{
+            batch_size: self.batch_size,
+            retry: RetryPolicy{ retries: self.retries, delay: Duration::from_secs(self.retry_delay) },
+            timeout: (self.timeout > 0).then(|| Duration::from_secs(self.timeout)),
+            cancel: cancel.clone(),
+            passphrase: env::var("WARP_PASSPHRASE").ok(),
+            verify: false,
+            wait: false,
+            changes: changes.cloned()
+        };
+        let mut callbacks = SyncCallbacks{
+            resolver: match self.on_conflict {
+                Some(prefer) => Box::new(Preference::from(prefer)) as Box<dyn ConflictResolver>,
+                None => Box::new(StaticResolver(Resolution::Skip))
+            },
+            on_event: Box::new(|event| {
+                if let SyncEvent::ActionFailed(failed) = event {
+                    Self::log(name, &format!("failed {} ({}): {}", failed.action.path, failed.action.action, failed.error));
+                }
+            })
+        };
+
+        return session.sync(config, &options, &mut callbacks).map_err(|e| e.to_string());
+    }
+
+    fn summary(report: &SyncReport) -> String {
+        let count = |action: ActionType| report.applied.iter().filter(|a| a.action == action).count();
+        let hint = match report.deferred.is_empty() {
+            true => "",
+            false => ", see `conflicts` and `resolve` to settle the conflicts"
+        };
+        return format!(
+            "synced ({} uploaded, {} downloaded, {} deleted locally, {} deleted remotely, {} failed, {} conflicts deferred, {} left undone){}",
+            count(ActionType::Local2Remote),
+            count(ActionType::Remote2Local),
+            count(ActionType::DelLocal),
+            count(ActionType::DelRemote),
+            report.failed.len(),
+            report.deferred.len(),
+            report.undone.len(),
+            hint
+        );
+    }
+
+    fn log(name: &str, message: &str) {
+        let now = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+        match json_output() {
+            true => { print_json(json!({ "time": now, "config": name, "message": message })); }
+            false => { println!("[{now}] {name}: {message}"); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use super::*;
+
+    fn link(next_sync: Instant) -> Link {
+        return Link{ next_sync, watcher: None };
+    }
+
+    #[test]
+    fn slow_link_does_not_hold_back_the_others() {
+        let now = Instant::now();
+        let mut links = HashMap::from([
+            ("slow".to_string(), link(now)),
+            ("fast".to_string(), link(now)),
+            ("later".to_string(), link(now + Duration::from_secs(3600)))
+        ]);
+        let counts: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+        let released = AtomicBool::new(false);
+
+        let sync = |name: &str, _: Option<HashSet<String>>| -> Synced {
+            *counts.lock().unwrap().entry(name.to_string()).or_default() += 1;
+            if name != "slow" { return Synced{ interval: Duration::ZERO, written: HashSet::new() }; }
+            while !released.load(Ordering::Relaxed) { thread::sleep(Duration::from_millis(5)); }
+            return Synced{ interval: Duration::from_secs(3600), written: HashSet::new() };
+        };
+        let count = |name: &str| counts.lock().unwrap().get(name).copied().unwrap_or(0);
+
+        thread::scope(|s| {
+            let mut running = Running::new();
+            for _ in 0..10 {
+                schedule(s, &mut links, &mut running, Duration::ZERO, &sync);
+                thread::sleep(Duration::from_millis(20));
+            }
+
+            // the fast link kept being synced while the slow one was still running
+            assert_eq!(count("slow"), 1);
+            assert!(count("fast") >= 3);
+            assert_eq!(count("later"), 0);
+
+            released.store(true, Ordering::Relaxed);
+            while running.contains_key("slow") {
+                schedule(s, &mut links, &mut running, Duration::ZERO, &sync);
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(count("slow"), 1);
+            assert!(links["slow"].next_sync > Instant::now());
+        });
+    }
+}
@@ -1,7 +1,9 @@
+use std::io::ErrorKind;
 use clap::Args;
+use serde_json::json;
 use warp::configs::Config;
 use std::fs::remove_dir_all;
-use crate::cmds::Cmd;
+use crate::cmds::{Cmd, Outcome, fatal, json_output, print_json};
 
 #[derive(Args)]
 pub struct CmdDelete {
@@ -15,13 +17,16 @@ pub struct CmdDelete {
 
 
 impl Cmd for CmdDelete {
-    fn execute(&self) {
-        let config = Config::load(&self.name);
-
-        if config.is_err() { println!("Invalid name: '{}'", &self.name); return;}
-        let config = config.unwrap();
+    fn execute(&self) -> Outcome {
+        let config = match Config::load(&self.name) {
+            Ok(config) => config,
+            Err(_) => { return fatal(ErrorKind::NotFound, &format!("Invalid name: '{}'", &self.name)); }
+        };
 
         remove_dir_all(config.link_path).unwrap();
         if self.clean { remove_dir_all(config.local).unwrap(); }
+
+        if json_output() { print_json(json!({ "deleted": self.name, "cleaned": self.clean })); }
+        return Outcome::Success;
     }
 }
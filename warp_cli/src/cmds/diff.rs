@@ -0,0 +1,103 @@
+use std::{env, fs, path, process};
+use std::path::Path;
+use clap::Args;
+use serde_json::{Value, json};
+use similar::TextDiff;
+use time::format_description::well_known::Rfc3339;
+use warp::{backend::RFileInfo, configs::Config, session::{Session, SyncOptions}};
+use crate::cmds::{Cmd, Outcome, config_of, fatal, json_output, passphrase_of, print_json};
+
+#[derive(Args)]
+pub struct CmdDiff {
+    /// File to compare, relative to the local folder of the config
+    path: String,
+
+    /// Name of the config holding the file. If not set, the config of the current folder is used
+    #[arg(short, long)]
+    name: Option<String>
+}
+
+
+impl Cmd for CmdDiff {
+    fn execute(&self) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
+
+        let path = self.relative_path(&config);
+        let temp = env::temp_dir().join(format!("warp-diff-{}", process::id()));
+        let options = SyncOptions{ passphrase: passphrase_of(&config), ..SyncOptions::default() };
+        let res = Session::new(1).fetch(&config, &path, &temp, &options);
+        let contents = res.and_then(|(local, remote)| {
+            let remote_content = if remote.is_some() { fs::read(&temp)? } else { Vec::new() };
+            let local_content = if local.is_some() { fs::read(Path::new(&config.local).join(&path))? } else { Vec::new() };
+            return Ok((local, remote, local_content, remote_content));
+        });
+        let _ = fs::remove_file(&temp);
+
+        let (local, remote, local_content, remote_content) = match contents {
+            Ok(contents) => contents,
+            Err(e) => { return fatal(e.kind(), &format!("Error while fetching {path}: {e}")); }
+        };
+
+        // text files present on both sides are compared line by line
+        let diff = match (&local, &remote, text(&local_content), text(&remote_content)) {
+            (Some(_), Some(_), Some(local_text), Some(remote_text)) => Some(
+                TextDiff::from_lines(remote_text, local_text).unified_diff()
+                    .header(&format!("remote/{path}"), &format!("local/{path}"))
+                    .to_string()
+            ),
+            _ => None
+        };
+
+        if json_output() {
+            print_json(json!({ "path": path, "local": local.as_ref().map(file_json), "remote": remote.as_ref().map(file_json), "diff": diff }));
+            return Outcome::Success;
+        }
+
+        match (&local, &remote, diff) {
+            (None, None, _) => { println!("{path} exists on neither side"); }
+            (Some(_), None, _) => { println!("{path} only exists locally"); }
+            (None, Some(_), _) => { println!("{path} only exists on the remote"); }
+            (_, _, Some(diff)) => { print!("{diff}"); }
+            (Some(local), Some(remote), None) => { Self::print_metadata(&path, local, remote); }
+        }
+        return Outcome::Success;
+    }
+}
+
+impl CmdDiff {
+    /// Paths inside the local folder are accepted too, as given from the current folder
+    fn relative_path(&self, config: &Config) -> String {
+        let absolute = path::absolute(&self.path).unwrap_or_else(|_| self.path.clone().into());
+        return match absolute.strip_prefix(&config.local) {
+            Ok(relative) => relative.to_str().unwrap_or(&self.path).replace('\\', "/"),
+            Err(_) => self.path.clone()
+        };
+    }
+
+    fn print_metadata(path: &str, local: &RFileInfo, remote: &RFileInfo) {
+        let same = |same: bool| if same { "" } else { "  *" };
+        let time = |file: &RFileInfo| file.mod_time.format(&Rfc3339).unwrap_or_default();
+
+        println!("Binary file {path}");
+        println!("{:10}{:42}remote", "", "local");
+        println!("{:10}{:<42}{}{}", "size", local.size, remote.size, same(local.size == remote.size));
+        println!("{:10}{:42}{}{}", "modified", time(local), time(remote), same(local.mod_time == remote.mod_time));
+        for kind in ["md5", "sha1"] {
+            let (l, r) = (local.hashes.get(kind), remote.hashes.get(kind));
+            println!("{:10}{:42}{}{}", kind, l.map_or("-", |h| h), r.map_or("-", |h| h), same(l == r));
+        }
+    }
+}
+
+fn file_json(file: &RFileInfo) -> Value {
+    return json!({ "size": file.size, "mod_time": file.mod_time.format(&Rfc3339).unwrap_or_default(), "hashes": file.hashes });
+}
+
+/// Content of text files. Files with NUL bytes or not encoded in UTF-8 are considered binary
+fn text(content: &[u8]) -> Option<&str> {
+    if content.contains(&0) { return None; }
+    return std::str::from_utf8(content).ok();
+}
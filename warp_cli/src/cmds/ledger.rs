@@ -0,0 +1,42 @@
+use clap::{Args, Subcommand};
+use warp::action::Preference;
+use crate::cmds::{Cmd, CmdSync, Outcome, PreferArg};
+
+#[derive(Args)]
+pub struct CmdLedger {
+    #[command(subcommand)]
+    command: LedgerCommands
+}
+
+#[derive(Subcommand)]
+enum LedgerCommands {
+    /// Rebuilds the ledger by comparing the local and remote files by size and hash
+    Rebuild(CmdLedgerRebuild)
+}
+
+#[derive(Args)]
+pub struct CmdLedgerRebuild {
+    /// Name of the config to rebuild. If not set, the config of the current folder is used
+    name: Option<String>,
+    /// The side to keep for files that differ. If not set, they are handled as conflicts
+    #[arg(short, long, value_enum)]
+    prefer: Option<PreferArg>,
+    /// If not set, the rebuild will also automatically sync
+    #[arg(short, long, action=clap::ArgAction::SetTrue)]
+    no_sync: bool
+}
+
+
+impl Cmd for CmdLedger {
+    fn execute(&self) -> Outcome {
+        match &self.command {
+            LedgerCommands::Rebuild(d) => { d.execute() }
+        }
+    }
+}
+
+impl Cmd for CmdLedgerRebuild {
+    fn execute(&self) -> Outcome {
+        return CmdSync::new(self.name.as_deref()).adopt(self.prefer.map(Preference::from), !self.no_sync);
+    }
+}
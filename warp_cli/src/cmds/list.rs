@@ -1,28 +1,41 @@
+use std::io::ErrorKind;
 use clap::Args;
+use serde_json::{Value, json};
 use warp::configs::{Config};
 
-use crate::cmds::Cmd;
+use crate::cmds::{Cmd, Outcome, fatal, json_output, print_json};
 
 #[derive(Args)]
 pub struct CmdList {}
 
 
 impl Cmd for CmdList {
-    fn execute(&self) {
+    fn execute(&self) -> Outcome {
+        let mut names = Config::get_all_names().into_iter().collect::<Vec<String>>();
+        names.sort();
 
-        println!("{:12}| {}", "Name", "Paths");
-        let names = Config::get_all_names();
-        for name in names{
-            let config = Config::load(&name);
-            if config.is_err() {
-                println!("Error while trying to open configs for: '{name}'");
-                return;
+        let mut configs = Vec::new();
+        for name in names {
+            match Config::load(&name) {
+                Ok(config) => { configs.push((name, config)); }
+                Err(_) => { return fatal(ErrorKind::InvalidData, &format!("Error while trying to open configs for: '{name}'")); }
             }
+        }
 
-            let config = config.unwrap();
+        if json_output() {
+            print_json(Value::Array(configs.iter().map(|(name, config)| json!({
+                "name": name, "local": config.local, "remote": config.remote,
+                "encrypted": config.crypt.is_some(), "verify": config.verify
+            })).collect()));
+            return Outcome::Success;
+        }
+
+        println!("{:12}| {}", "Name", "Paths");
+        for (name, config) in configs {
             println!("{:-<27}", "");
             println!("{:12}| {}", &name, &config.local);
             println!("{:12}| {}", "", &config.remote);
         }
+        return Outcome::Success;
     }
 }
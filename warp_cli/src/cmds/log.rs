@@ -0,0 +1,115 @@
+use clap::Args;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use warp::history::HistoryEntry;
+use crate::cmds::{Cmd, Outcome, config_of, fatal, json_output, print_json};
+
+#[derive(Args)]
+pub struct CmdLog {
+    /// Name of the config. If not set, the config of the current folder is used
+    name: Option<String>,
+
+    /// Only shows the syncs that touched this file, relative to the local folder of the config
+    #[arg(short, long)]
+    path: Option<String>,
+
+    /// Number of syncs to show, starting from the latest. If 0, every sync is shown
+    #[arg(short = 'n', long, default_value_t=10)]
+    count: usize,
+
+    /// Lists the actions applied on every file
+    #[arg(short, long, action=clap::ArgAction::SetTrue)]
+    files: bool
+}
+
+
+impl Cmd for CmdLog {
+    fn execute(&self) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
+
+        let history = match HistoryEntry::load_all(&config.link_path) {
+            Ok(history) => history,
+            Err(e) => { return fatal(e.kind(), &format!("Error while reading the history: {e}")); }
+        };
+
+        match (&self.path, json_output()) {
+            (None, false) => { self.print_syncs(&history); }
+            (Some(path), false) => { self.print_file(&history, path); }
+            (path, true) => {
+                let path = path.as_deref().map(|p| p.trim_start_matches("./"));
+                let entries = self.latest(history.iter().filter(|entry| path.is_none_or(|path| touches(entry, path))))
+                    .collect::<Vec<&HistoryEntry>>();
+                print_json(serde_json::to_value(entries).unwrap_or_default());
+            }
+        }
+        return Outcome::Success;
+    }
+}
+
+impl CmdLog {
+    fn print_syncs(&self, history: &[HistoryEntry]) {
+        if history.is_empty() { println!("No sync recorded yet"); }
+
+        for entry in self.latest(history.iter()) {
+            let counts = entry.counts.iter().map(|(action, count)| format!("{count} {action}")).collect::<Vec<String>>();
+            println!(
+                "{} ({}s) {} bytes{}{}",
+                time(entry.started), (entry.finished - entry.started).whole_seconds(), entry.bytes,
+                if counts.is_empty() { String::new() } else { format!(", {}", counts.join(", ")) },
+                if entry.errors.is_empty() { String::new() } else { format!(", {} failed", entry.errors.len()) }
+            );
+
+            for conflict in &entry.conflicts {
+                println!("    conflict {} ({}): {}", conflict.path, conflict.conflict, conflict.resolution);
+            }
+            for error in &entry.errors {
+                println!("    failed {} ({}): {}", error.path, error.action, error.error.as_deref().unwrap_or(""));
+            }
+            if self.files {
+                entry.files.iter().for_each(|file| println!("    {} {}", file.action, file.path));
+            }
+        }
+    }
+
+    /// Shows the syncs that touched `path`, and when the file was last transferred or deleted
+    fn print_file(&self, history: &[HistoryEntry], path: &str) {
+        let path = path.trim_start_matches("./");
+        let last = |actions: &[&str]| history.iter().rev()
+            .find(|entry| entry.files.iter().any(|f| f.path == path && actions.contains(&f.action.as_str())))
+            .map_or("never".to_string(), |entry| time(entry.started));
+        println!("last transferred: {}", last(&["upload", "download"]));
+        println!("last deleted:     {}", last(&["delete_local", "delete_remote"]));
+
+        let touched = history.iter().filter(|entry| touches(entry, path));
+        for entry in self.latest(touched) {
+            let started = time(entry.started);
+            for conflict in entry.conflicts.iter().filter(|c| c.path == path) {
+                println!("{started} conflict ({}): {}", conflict.conflict, conflict.resolution);
+            }
+            for file in entry.files.iter().filter(|f| f.path == path) {
+                println!("{started} {}", file.action);
+            }
+            for file in entry.errors.iter().filter(|f| f.path == path) {
+                println!("{started} {} failed: {}", file.action, file.error.as_deref().unwrap_or(""));
+            }
+        }
+    }
+
+    /// The `count` latest entries, latest first
+    fn latest<'a>(&self, entries: impl DoubleEndedIterator<Item=&'a HistoryEntry>) -> impl Iterator<Item=&'a HistoryEntry> {
+        let count = if self.count == 0 { usize::MAX } else { self.count };
+        return entries.rev().take(count);
+    }
+}
+
+/// Checks if a sync did anything on `path`
+fn touches(entry: &HistoryEntry, path: &str) -> bool {
+    return entry.files.iter().chain(&entry.errors).any(|f| f.path == path) || entry.conflicts.iter().any(|c| c.path == path);
+}
+
+fn time(time: OffsetDateTime) -> String {
+    return time.format(&Rfc3339).unwrap_or_default();
+}
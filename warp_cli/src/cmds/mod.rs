@@ -1,11 +1,27 @@
 mod cmd;
+mod conflicts;
 mod create;
+mod daemon;
 mod delete;
+mod diff;
+mod ledger;
 mod list;
+mod log;
+mod output;
+mod resolve;
+mod status;
 mod sync;
 
-pub use cmd::Cmd;
+pub use cmd::{Cmd, PreferArg, config_of, interactive, interrupt_token, passphrase_of, read_passphrase, set_non_interactive};
+pub use conflicts::CmdConflicts;
 pub use create::CmdCreate;
+pub use daemon::CmdDaemon;
 pub use delete::CmdDelete;
+pub use diff::CmdDiff;
+pub use ledger::CmdLedger;
 pub use list::CmdList;
+pub use log::CmdLog;
+pub use output::{Outcome, OutputArg, action_json, event_json, fatal, json_output, print_json, report_json, set_output};
+pub use resolve::CmdResolve;
+pub use status::CmdStatus;
 pub use sync::CmdSync;
\ No newline at end of file
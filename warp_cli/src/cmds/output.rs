@@ -0,0 +1,107 @@
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use clap::ValueEnum;
+use serde_json::{Value, json};
+use warp::{action::{Action, ActionType, SyncReport}, event::{Side, SyncEvent}};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Format of what the commands print
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum OutputArg {
+    /// Human readable text
+    Text,
+    /// JSON documents, or JSON lines for the events of a sync
+    Json
+}
+
+/// Result of a command, which is the exit code of the process
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Success = 0,
+    /// The command could not run at all
+    Fatal = 1,
+    /// Some files could not be synced
+    PartialFailure = 2,
+    /// Conflicts are left unresolved
+    Conflicts = 3
+}
+
+impl Outcome {
+    /// Outcome of a sync. Failures take precedence over conflicts
+    pub fn of(report: &SyncReport) -> Self {
+        if !report.is_success() { return Outcome::PartialFailure; }
+        if !report.deferred.is_empty() { return Outcome::Conflicts; }
+        return Outcome::Success;
+    }
+}
+
+pub fn set_output(output: OutputArg) {
+    JSON.store(output == OutputArg::Json, Ordering::Relaxed);
+}
+
+/// Checks if the output is JSON, in which case nothing else may be printed
+pub fn json_output() -> bool {
+    return JSON.load(Ordering::Relaxed);
+}
+
+/// Prints a JSON value on a single line
+pub fn print_json(value: Value) {
+    println!("{value}");
+}
+
+/// Prints an error that stops the command, as a JSON `error` event with JSON output
+pub fn fatal(kind: ErrorKind, message: &str) -> Outcome {
+    match json_output() {
+        true => { print_json(json!({ "event": "error", "kind": error_kind(kind), "message": message })); }
+        false => { println!("{message}"); }
+    }
+    return Outcome::Fatal;
+}
+
+fn error_kind(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound =>         { "not_found" }
+        ErrorKind::AlreadyExists =>    { "already_exists" }
+        ErrorKind::PermissionDenied => { "permission_denied" }
+        ErrorKind::WouldBlock =>       { "busy" }
+        ErrorKind::Interrupted =>      { "interrupted" }
+        ErrorKind::Unsupported =>      { "unsupported" }
+        _ =>                           { "other" }
+    }
+}
+
+pub fn action_json(action: &Action) -> Value {
+    return match &action.action {
+        ActionType::Error(conflict) => json!({ "path": action.path, "action": action.action.name(), "conflict": conflict }),
+        _ => json!({ "path": action.path, "action": action.action.name() })
+    };
+}
+
+pub fn report_json(report: &SyncReport) -> Value {
+    return json!({
+        "applied": report.applied.iter().map(action_json).collect::<Vec<Value>>(),
+        "failed": report.failed.iter()
+            .map(|f| json!({ "path": f.action.path, "action": f.action.action.name(), "error": f.error }))
+            .collect::<Vec<Value>>(),
+        "deferred": report.deferred.iter().map(action_json).collect::<Vec<Value>>(),
+        "undone": report.undone.iter().map(action_json).collect::<Vec<Value>>()
+    });
+}
+
+/// Events of a sync as JSON lines. The progress of single files is left out
+pub fn event_json(event: &SyncEvent) -> Option<Value> {
+    let side = |side: &Side| if side == &Side::Local { "local" } else { "remote" };
+
+    return match event {
+        SyncEvent::ListingStarted(s) => Some(json!({ "event": "listing_started", "side": side(s) })),
+        SyncEvent::ListingFinished(s, count) => Some(json!({ "event": "listing_finished", "side": side(s), "count": count })),
+        SyncEvent::Planned{ actions, bytes } => Some(json!({ "event": "planned", "actions": actions, "bytes": bytes })),
+        SyncEvent::ActionStarted(action) => Some(json!({ "event": "action_started", "path": action.path, "action": action.action.name() })),
+        SyncEvent::ActionVerified{ path, hashes } => Some(json!({ "event": "action_verified", "path": path, "hashes": hashes })),
+        SyncEvent::ActionFinished(action) => Some(json!({ "event": "action_finished", "path": action.path, "action": action.action.name() })),
+        SyncEvent::ActionFailed(f) => Some(json!({ "event": "action_failed", "path": f.action.path, "action": f.action.action.name(), "error": f.error })),
+        SyncEvent::Stats(stats) => Some(json!({ "event": "stats", "bytes": stats.bytes, "total_bytes": stats.total_bytes, "speed": stats.speed, "eta": stats.eta })),
+        SyncEvent::ActionProgress{ .. } => None
+    };
+}
@@ -0,0 +1,67 @@
+use clap::{Args, ValueEnum};
+use serde_json::json;
+use warp::{conflict::Resolution, session::{Session, SyncOptions}};
+use crate::cmds::{Cmd, CmdSync, Outcome, config_of, fatal, json_output, passphrase_of, print_json, report_json};
+
+#[derive(Args)]
+pub struct CmdResolve {
+    /// File of the conflict, relative to the local folder of the config
+    path: String,
+
+    /// Version of the file to keep
+    #[arg(short, long, value_enum)]
+    keep: KeepArg,
+
+    /// Name of the config. If not set, the config of the current folder is used
+    #[arg(short, long)]
+    name: Option<String>
+}
+
+/// Versions to keep when resolving a conflict
+#[derive(ValueEnum, Clone, Copy)]
+enum KeepArg {
+    Local,
+    Remote,
+    /// Keeps both versions by renaming the remote one
+    Both
+}
+
+impl From<KeepArg> for Resolution {
+    fn from(value: KeepArg) -> Self {
+        match value {
+            KeepArg::Local =>  { Resolution::KeepLocal }
+            KeepArg::Remote => { Resolution::KeepRemote }
+            KeepArg::Both =>   { Resolution::KeepBoth }
+        }
+    }
+}
+
+
+impl Cmd for CmdResolve {
+    fn execute(&self) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
+
+        let path = self.path.trim_start_matches("./");
+        let options = SyncOptions{ passphrase: passphrase_of(&config), ..SyncOptions::default() };
+        let res = Session::new(1).resolve_conflict(&config, path, self.keep.into(), &options, &mut |_| {});
+
+        let report = match res {
+            Ok(report) => report,
+            Err(e) => { return fatal(e.kind(), &format!("Error while resolving {path}: {e}")); }
+        };
+
+        let outcome = Outcome::of(&report);
+        match (json_output(), outcome) {
+            (true, _) => { print_json(json!({ "event": "report", "report": report_json(&report) })); }
+            (false, Outcome::Success) => { println!("Conflict of {path} resolved"); }
+            (false, _) => {
+                println!("The conflict of {path} could not be resolved");
+                CmdSync::print_report(&report, false);
+            }
+        }
+        return outcome;
+    }
+}
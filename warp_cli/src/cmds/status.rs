@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use clap::Args;
+use serde_json::{Value, json};
+use warp::{
+    action::{Action, ActionType}, session::{Session, SyncOptions}
+};
+use crate::cmds::{Cmd, Outcome, action_json, config_of, fatal, json_output, passphrase_of, print_json};
+
+#[derive(Args)]
+pub struct CmdStatus {
+    /// Name of the config to inspect. If not set, the config of the current folder is used
+    name: Option<String>
+}
+
+
+impl Cmd for CmdStatus {
+    fn execute(&self) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
+
+        let options = SyncOptions{ passphrase: passphrase_of(&config), ..SyncOptions::default() };
+        let actions = match Session::new(4).status(&config, &options) {
+            Ok(actions) => actions,
+            Err(e) => { return fatal(e.kind(), &format!("Error while comparing the config: {e}")); }
+        };
+        let outcome = if actions.iter().any(|a| a.action.is_error()) { Outcome::Conflicts } else { Outcome::Success };
+
+        if json_output() {
+            print_json(json!({
+                "local": config.local, "remote": config.remote,
+                "actions": actions.iter().map(action_json).collect::<Vec<Value>>()
+            }));
+            return outcome;
+        }
+
+        println!("{} <-> {}", config.local, config.remote);
+        if actions.is_empty() {
+            println!("Everything is in sync");
+            return outcome;
+        }
+
+        Self::print_actions(&actions);
+        let count = |f: fn(&ActionType) -> bool| actions.iter().filter(|a| f(&a.action)).count();
+        println!(
+            "\n{} uploads, {} downloads, {} deletions, {} conflicts",
+            count(|a| a == &ActionType::Local2Remote),
+            count(|a| a == &ActionType::Remote2Local),
+            count(|a| matches!(a, ActionType::DelLocal | ActionType::DelRemote)),
+            count(ActionType::is_error)
+        );
+        return outcome;
+    }
+}
+
+impl CmdStatus {
+    /// Prints the actions grouped by the directory of their file
+    fn print_actions(actions: &[Action]) {
+        let mut dirs: BTreeMap<&str, Vec<&Action>> = BTreeMap::new();
+        for action in actions {
+            let dir = action.path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+            dirs.entry(dir).or_default().push(action);
+        }
+
+        for (dir, actions) in dirs {
+            println!("\n{}/", if dir.is_empty() { "." } else { dir });
+            for action in actions {
+                let name = action.path.rsplit_once('/').map(|(_, name)| name).unwrap_or(&action.path);
+                match &action.action {
+                    ActionType::Local2Remote => { println!("    upload          {name}"); }
+                    ActionType::Remote2Local => { println!("    download        {name}"); }
+                    ActionType::DelLocal =>     { println!("    delete local    {name}"); }
+                    ActionType::DelRemote =>    { println!("    delete remote   {name}"); }
+                    ActionType::Error(err) =>   { println!("    conflict        {name} ({err})"); }
+                    ActionType::Nothing =>      {}
+                }
+            }
+        }
+    }
+}
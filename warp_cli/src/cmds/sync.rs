@@ -1,17 +1,19 @@
-use std::{io, io::{Read, Write}};
-use std::io::{stdout, Error, ErrorKind};
+use std::io::{stdout, Error, ErrorKind, Write};
 use std::path::Path;
 use clap::Args;
-use std::sync::mpsc;
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
 use termion::{clear, cursor, color};
 use warp::{
-    action::{ErrorType, ActionType, Action, gen_action_list},
-    rclone::{RClone, RFileInfo}, ledger::Ledger, configs::Config
+    action::{Preference, SyncReport},
+    engine::{CancelToken, RetryPolicy}, configs::Config, conflict::{ConflictResolver, Resolution, StaticResolver, TerminalResolver}, event::{Side, SyncEvent, TransferStats},
+    session::{Session, SyncCallbacks, SyncOptions}
 };
-use crate::cmds::Cmd;
+use crate::cmds::{Cmd, Outcome, config_of, event_json, fatal, interactive, interrupt_token, json_output, passphrase_of, print_json, report_json};
 
 #[derive(Args)]
 pub struct CmdSync {
@@ -25,136 +27,211 @@ pub struct CmdSync {
     /// Defines the number of element to put in a single rclone request if run in parallel mode.
     /// If 0 the maximum batch size will be used
     #[arg(short, long, default_value_t=8)]
-    batch_size: usize
+    batch_size: usize,
+
+    /// Number of times a failing batch is retried before looking for the failing files
+    #[arg(short, long, default_value_t=3)]
+    retries: usize,
+
+    /// Delay in seconds before the first retry. The delay doubles after every retry
+    #[arg(long, default_value_t=1)]
+    retry_delay: u64,
+
+    /// Time in seconds after which a single transfer is stopped and retried. If 0, transfers have no time limit
+    #[arg(long, default_value_t=0)]
+    timeout: u64,
+
+    /// Checks the content of every transferred file on both sides, even if the config does not ask for it
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    verify: bool,
+
+    /// If the config is already being synced by another process, waits for it to finish instead of failing
+    #[arg(short, long, action=clap::ArgAction::SetTrue)]
+    wait: bool,
+
+    /// Passphrase of the link when it was already asked for, as on creation
+    #[arg(skip)]
+    passphrase: Option<String>
 }
 
 
 impl Cmd for CmdSync {
-    fn execute(&self) {
-        let config = Self::get_config(&self.name);
+    fn execute(&self) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
 
-        if config.is_err() {
-            match &self.name {
-                None => { println!("Invalid location.\nPlease specify a config name or be in an existing config location"); return;}
-                Some(name) => { println!("Invalid config name: '{}'", name); return;}
-            }
-        }
+        let session = Session::new(self.thread_count);
+        let cancel = interrupt_token();
+        let mut progress = Progress{ plain: !interactive(), ..Progress::default() };
+        let mut callbacks = SyncCallbacks{
+            resolver: Self::resolver(),
+            on_event: Box::new(|event| progress.update(event))
+        };
 
+        let report = match session.sync(&config, &self.options(&config, &cancel), &mut callbacks) {
+            Ok(report) => report,
+            Err(e) => { return self.print_error(e); }
+        };
 
-        let config = config.unwrap();
+        match json_output() {
+            true => { print_json(json!({ "event": "report", "report": report_json(&report) })); }
+            false => { Self::print_report(&report, cancel.is_cancelled()); }
+        }
+        return Outcome::of(&report);
+    }
+}
 
-        let mut ledger = Ledger::load(&config.link_path);
-        let rclone = RClone::new(&config.local, &config.remote);
+impl CmdSync {
+    pub fn new(name: Option<&str>) -> Self {
+        Self{ name: name.map(str::to_string), thread_count: 4, batch_size: 8, retries: 3, retry_delay: 1, timeout: 0, verify: false, wait: false, passphrase: None}
+    }
 
-        let local =  rclone.local_list();
+    pub fn with_passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase;
+        return self;
+    }
 
-        let _rclone = rclone.clone();
-        let remote_future = thread::spawn(move || _rclone.remote_list());
-        Self::wait_for_remote(&remote_future);
-        let remote = remote_future.join().unwrap();
+    /// Rebuilds the ledger of the link from scratch by comparing both sides by size and hash.
+    /// Identical files are recorded straight into the ledger and differing files are reported.
+    /// If `sync` is set, the remaining actions are applied as a normal sync would
+    pub fn adopt(&self, prefer: Option<Preference>, sync: bool) -> Outcome {
+        let config = match config_of(&self.name) {
+            Ok(config) => config,
+            Err(outcome) => { return outcome; }
+        };
 
-        let mut actions = gen_action_list(&local, &remote, &ledger);
-        Self::handle_errors(&mut actions);
+        let session = Session::new(self.thread_count);
+        let cancel = interrupt_token();
+        let mut progress = Progress{ plain: !interactive(), ..Progress::default() };
+        let mut callbacks = SyncCallbacks{
+            resolver: Self::resolver(),
+            on_event: Box::new(|event| progress.update(event))
+        };
 
-        let _actions = actions.clone();
-        let batch_size = self.batch_size.clone();
-        let thread_count = self.thread_count.clone();
+        let report = match session.adopt(&config, prefer, sync, &self.options(&config, &cancel), &mut callbacks) {
+            Ok(report) => report,
+            Err(e) => { return self.print_error(e); }
+        };
 
-        let (tx, rx) = mpsc::channel();
-        let rclone = thread::spawn(move || {
-            rclone.apply_actions(&_actions, Some(tx), thread_count, batch_size);
-        });
+        let outcome = match &report.sync {
+            Some(sync) => Outcome::of(sync),
+            None if report.differing.is_empty() => Outcome::Success,
+            None => Outcome::Conflicts
+        };
+        if json_output() {
+            print_json(json!({
+                "event": "adoption", "adopted": report.adopted, "differing": report.differing,
+                "report": report.sync.as_ref().map(report_json)
+            }));
+            return outcome;
+        }
 
-        // getting the total number of steps to take
-        let total : usize = 2 * actions.iter().filter(|a| a.action != ActionType::Nothing).count();
-        let mut steps: usize = 0;
-        for (state, file, action_type) in rx {
-            ledger.update_ledger(&file, action_type);
-            ledger.save(&config.link_path);
+        println!("\n{} identical files adopted", report.adopted);
+        if !report.differing.is_empty() {
+            println!("{} files differ between local and remote:", report.differing.len());
+            report.differing.iter().for_each(|path| println!("    {}", path));
+        }
+        if let Some(report) = &report.sync { Self::print_report(report, cancel.is_cancelled()); }
+        return outcome;
+    }
 
-            steps += 1;
-            Self::update_cli(state, &file, steps, total);
-            if steps == total { break; }
+    fn options(&self, config: &Config, cancel: &CancelToken) -> SyncOptions {
+        return SyncOptions{
+            batch_size: self.batch_size,
+            retry: RetryPolicy{ retries: self.retries, delay: Duration::from_secs(self.retry_delay) },
+            timeout: (self.timeout > 0).then(|| Duration::from_secs(self.timeout)),
+            cancel: cancel.clone(),
+            passphrase: self.passphrase(config),
+            verify: self.verify,
+            wait: self.wait,
+            changes: None
         }
+    }
 
-        rclone.join().unwrap();
-        
-        let new_ledger = Ledger::ledger_from(&actions);
-        new_ledger.save(&config.link_path)
+    /// Conflicts are asked about on the terminal, or left unresolved when nobody can answer
+    fn resolver() -> Box<dyn ConflictResolver> {
+        return match interactive() {
+            true => Box::new(TerminalResolver),
+            false => Box::new(StaticResolver(Resolution::Skip))
+        };
     }
-}
 
-impl CmdSync {
-    pub fn new(name: &str) -> Self {
-        Self{ name: Some(name.to_string()), thread_count: 4, batch_size: 8}
+    fn passphrase(&self, config: &Config) -> Option<String> {
+        return self.passphrase.clone().or_else(|| passphrase_of(config));
     }
 
-    fn handle_errors(actions: &mut Vec<Action>) {
-        let errors = actions.iter_mut().filter(|a| matches!(a.action, ActionType::Error(_)));
-        for error in errors {
-            println!("\n{} in file: {}", error.action,  error.path);
-            loop {
-                print!("keep the REMOTE or LOCAL? (r/l): ");
-                stdout().flush().expect("");
-
-                let mut buffer: [u8; 1] = [0];
-                let res = io::stdin().read(&mut buffer);
-                if res.is_err() { continue; }
-
-                let input = buffer[0];
-                match input {
-                    0x6C => {
-                        // if the local action is chosen, and it was a deletion, the deletion needs to
-                        // be propagated to the remote
-                        error.action = if matches!(error.action, ActionType::Error(ErrorType::DelAndMod)) {
-                            ActionType::DelRemote
-                        } else { // otherwise, the local has a file and it needs to be copied to the remote
-                            ActionType::Local2Remote
-                        };
-                        break;
-                    }
-                    0x72 => {
-                        // if the remote action is chosen, and it was a deletion, the deletion needs to
-                        // be propagated to the local
-                        error.action = if matches!(error.action, ActionType::Error(ErrorType::ModAndDel)) {
-                            ActionType::DelLocal
-                        } else { // otherwise, the local has a file and it needs to be copied to the remote
-                            ActionType::Remote2Local
-                        };
-                        break;
-                    }
-                    _ => { continue; }
-                }
+    fn print_error(&self, e: Error) -> Outcome {
+        return match e.kind() {
+            ErrorKind::WouldBlock => fatal(e.kind(), &format!("This config is already being synced: {e}.\nUse --wait to wait for it to finish")),
+            ErrorKind::Interrupted => fatal(e.kind(), &format!("\nSync stopped before anything was transferred: {e}")),
+            _ => fatal(e.kind(), &format!("Error while syncing: {e}"))
+        };
+    }
+
+    /// If the sync was `interrupted`, the actions it did not get to are listed too
+    pub fn print_report(report: &SyncReport, interrupted: bool) {
+        if interrupted {
+            println!("\nSync interrupted, {} actions applied and {} left undone for the next sync:", report.applied.len(), report.undone.len());
+            report.undone.iter().for_each(|a| println!("    {} ({})", a.path, a.action));
+        }
+
+        if !report.failed.is_empty() {
+            println!("\n{} files could not be synced:", report.failed.len());
+            for failed in &report.failed {
+                println!("    {} ({}): {}", failed.action.path, failed.action.action, failed.error);
             }
         }
+
+        if !report.deferred.is_empty() {
+            println!("\n{} conflicts were left unresolved, see `conflicts` and `resolve` to settle them", report.deferred.len());
+        }
     }
 
-    fn update_cli(state: bool, name: &str, done: usize, total: usize) {
-        let (c, r) = termion::terminal_size().unwrap();
+    fn update_cli(message: Option<(&str, &str)>, done: usize, total: usize, stats: &TransferStats, total_bytes: u64) {
+        let (c, r) = termion::terminal_size().unwrap_or((80, 24));
 
-        let prefix = match state { true => "starting", false => "finished" };
-        println!("{}{}{} {}", cursor::Goto(1, r), clear::CurrentLine, prefix, name);
+        if let Some((prefix, name)) = message {
+            println!("{}{}{} {}", cursor::Goto(1, r), clear::CurrentLine, prefix, name);
+        }
 
-        let percent_space = usize::from(c - 17);
-        let prc_progress = (done * percent_space) / total;
-        let percent = (done * 100) / total;
+        // the progress is measured in bytes when there is something to copy, otherwise in actions
+        let (done_part, total_part) = match total_bytes {
+            0 => (done as u64, total.max(1) as u64),
+            _ => (stats.bytes.min(total_bytes), total_bytes)
+        };
+
+        let eta = match stats.speed > 0.0 {
+            true => format!("{}s", (total_bytes.saturating_sub(stats.bytes) as f64 / stats.speed) as u64),
+            false => "-".to_string()
+        };
+        let details = format!(
+            " {done}/{total} files {}/{} {}/s ETA {eta}",
+            human_bytes(stats.bytes), human_bytes(total_bytes), human_bytes(stats.speed as u64)
+        );
+
+        let percent_space = usize::from(c).saturating_sub(17 + details.len());
+        let prc_progress = (done_part as usize * percent_space) / total_part as usize;
+        let percent = (done_part * 100) / total_part;
 
         print!(
-            "{}{}{} Progress:{: >3}%{}{} [{: <percent_space$}]",
-            cursor::Goto(1, r),
+            "{}{}{}{} Progress:{: >3}%{}{} [{: <percent_space$}]{}",
+            cursor::Goto(1, r), clear::CurrentLine,
             color::Fg(color::Black), color::Bg(color::Green),
             percent,
             color::Fg(color::Reset), color::Bg(color::Reset),
-            format!("{:#>prc_progress$}", "")
+            format!("{:#>prc_progress$}", ""),
+            details
         );
         stdout().flush().unwrap();
     }
 
-    fn wait_for_remote(remote_future: &JoinHandle<Vec<RFileInfo>>) {
+    fn wait_for_remote(stop: &AtomicBool) {
         let mut state = "|";
 
         print!("getting remote file list. This may take a while... |");
-        while !remote_future.is_finished() {
+        while !stop.load(Ordering::Relaxed) {
             state = match state { "|" => "/", "/" => "-",  "-" => "\\",  "\\" => "|",  _ => "|" };
             print!("{}{}", cursor::Left(1), state);
             stdout().flush().unwrap();
@@ -162,7 +239,7 @@ impl CmdSync {
         }
     }
 
-    fn get_config(name: &Option<String>) -> Result<Config, Error> {
+    pub fn get_config(name: &Option<String>) -> Result<Config, Error> {
         // if the config name is given try loading it
         if name.is_some() {
             return Config::load(name.as_deref().unwrap());
@@ -181,4 +258,93 @@ impl CmdSync {
             Some(name) => Config::load(name)
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// State of the progress shown in the terminal during a sync
+#[derive(Default)]
+struct Progress {
+    /// Logs plain lines instead of drawing a progress bar, for output that is not a terminal
+    plain: bool,
+    done: usize,
+    total: usize,
+    total_bytes: u64,
+    stats: TransferStats,
+    spinner: Option<(Arc<AtomicBool>, JoinHandle<()>)>
+}
+
+impl Progress {
+    fn update(&mut self, event: SyncEvent) {
+        if json_output() {
+            if let Some(event) = event_json(&event) { print_json(event); }
+            return;
+        }
+        if self.plain { return self.log(event); }
+
+        match event {
+            SyncEvent::ListingStarted(Side::Remote) => {
+                let stop = Arc::new(AtomicBool::new(false));
+                let _stop = stop.clone();
+                self.spinner = Some((stop, thread::spawn(move || CmdSync::wait_for_remote(&_stop))));
+            }
+            SyncEvent::ListingFinished(Side::Remote, _) => {
+                if let Some((stop, spinner)) = self.spinner.take() {
+                    stop.store(true, Ordering::Relaxed);
+                    spinner.join().unwrap();
+                }
+            }
+            SyncEvent::Planned{ actions, bytes } => {
+                self.total = actions;
+                self.total_bytes = bytes;
+            }
+            SyncEvent::ActionStarted(action) => {
+                CmdSync::update_cli(Some(("starting", &action.path)), self.done, self.total, &self.stats, self.total_bytes);
+            }
+            SyncEvent::ActionFinished(action) => {
+                self.done += 1;
+                CmdSync::update_cli(Some(("finished", &action.path)), self.done, self.total, &self.stats, self.total_bytes);
+            }
+            SyncEvent::ActionFailed(failed) => {
+                self.done += 1;
+                CmdSync::update_cli(Some(("failed", &failed.action.path)), self.done, self.total, &self.stats, self.total_bytes);
+            }
+            SyncEvent::Stats(stats) => {
+                self.stats = stats;
+                CmdSync::update_cli(None, self.done, self.total, &self.stats, self.total_bytes);
+            }
+            _ => {}
+        }
+    }
+
+    /// Plain line-based logging of the events
+    fn log(&mut self, event: SyncEvent) {
+        match event {
+            SyncEvent::ListingStarted(Side::Remote) => { println!("listing remote files"); }
+            SyncEvent::ListingFinished(side, count) => { println!("{count} {} files listed", if side == Side::Local { "local" } else { "remote" }); }
+            SyncEvent::Planned{ actions, bytes } => {
+                self.total = actions;
+                println!("{actions} actions to apply, {} to transfer", human_bytes(bytes));
+            }
+            SyncEvent::ActionFinished(action) => {
+                self.done += 1;
+                println!("[{}/{}] {} {}", self.done, self.total, action.action, action.path);
+            }
+            SyncEvent::ActionFailed(failed) => {
+                self.done += 1;
+                println!("[{}/{}] failed {} {}: {}", self.done, self.total, failed.action.action, failed.action.path, failed.error);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    return format!("{:.1} {}", size, units[unit]);
+}
@@ -1,34 +1,65 @@
 mod cmds;
-use cmds::Cmd;
+use std::process;
+use cmds::{Cmd, Outcome, OutputArg};
 
 use clap::{Parser, Subcommand};
-use cmds::{CmdCreate, CmdDelete, CmdList, CmdSync};
+use cmds::{CmdConflicts, CmdCreate, CmdDaemon, CmdDelete, CmdDiff, CmdLedger, CmdList, CmdLog, CmdResolve, CmdStatus, CmdSync};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Warp {
     #[command(subcommand)]
     command: Commands,
+
+    /// Never prompts and logs plain lines instead of drawing progress bars. Conflicts are left
+    /// unresolved. Implied when not run from a terminal
+    #[arg(long, global = true, action=clap::ArgAction::SetTrue)]
+    non_interactive: bool,
+
+    /// Format of the output. JSON output is never interactive
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    output: OutputArg
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Lists the conflicts left unresolved by past syncs
+    Conflicts(CmdConflicts),
     /// Creates a config between a local folder and a remote
     Create(CmdCreate),
+    /// Periodically syncs every config in the foreground
+    Daemon(CmdDaemon),
     /// Deletes an existing config
     Delete(CmdDelete),
+    /// Compares the local and remote versions of a file
+    Diff(CmdDiff),
+    /// Manages the ledger of a config
+    Ledger(CmdLedger),
     /// Lists existing configs
     List(CmdList),
+    /// Shows the past syncs of a config
+    Log(CmdLog),
+    /// Resolves a conflict left by a past sync
+    Resolve(CmdResolve),
+    /// Shows what the next sync of a config would do, without transferring anything
+    Status(CmdStatus),
     /// syncs a config
     Sync(CmdSync)
 }
 
 impl Commands {
-    fn run(&self) {
+    fn run(&self) -> Outcome {
         match &self {
+            Commands::Conflicts(d) => { d.execute() }
             Commands::Create(d) => { d.execute() }
+            Commands::Daemon(d) => { d.execute() }
             Commands::Delete(d) => { d.execute() }
+            Commands::Diff  (d) => { d.execute() }
+            Commands::Ledger(d) => { d.execute() }
             Commands::List  (d) => { d.execute() }
+            Commands::Log   (d) => { d.execute() }
+            Commands::Resolve(d) => { d.execute() }
+            Commands::Status(d) => { d.execute() }
             Commands::Sync  (d) => { d.execute() }
         }
     }
@@ -36,5 +67,7 @@ impl Commands {
 
 fn main() {
     let cli = Warp::parse();
-    cli.command.run()
+    if cli.non_interactive { cmds::set_non_interactive(); }
+    cmds::set_output(cli.output);
+    process::exit(cli.command.run() as i32)
 }
\ No newline at end of file